@@ -0,0 +1,105 @@
+//! A static ActivityPub actor, so the blog can be followed from Mastodon and
+//! the like. There's no server, so nothing can be sent to it: followers poll
+//! the outbox.
+//!
+//! These need serving with the right content types: `application/jrd+json`
+//! for `/.well-known/webfinger`, and `application/activity+json` for the
+//! rest.
+
+use serde_json::{json, Value};
+
+use crate::{config, config::ActivityPubConfig, PageContext};
+
+pub const WEBFINGER_PATH: &str = "/.well-known/webfinger";
+pub const ACTOR_PATH: &str = "/actor.json";
+pub const OUTBOX_PATH: &str = "/outbox.json";
+
+fn host() -> String {
+    let base_url = &config().base_url;
+    let rest = base_url.split_once("//").map_or(base_url.as_str(), |p| p.1);
+    rest.split('/').next().unwrap_or_default().to_owned()
+}
+
+/// The WebFinger response for `acct:<username>@<host>`, pointing at the
+/// actor.
+pub fn webfinger(options: &ActivityPubConfig) -> Value {
+    let actor = config().absolute_url(ACTOR_PATH);
+    json!({
+        "subject": format!("acct:{}@{}", options.username, host()),
+        "aliases": [actor, config().absolute_url("/")],
+        "links": [
+            {
+                "rel": "self",
+                "type": "application/activity+json",
+                "href": actor,
+            },
+            {
+                "rel": "http://webfinger.net/rel/profile-page",
+                "type": "text/html",
+                "href": config().absolute_url("/"),
+            },
+        ],
+    })
+}
+
+pub fn actor(options: &ActivityPubConfig) -> Value {
+    let config = config();
+    let mut actor = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": config.absolute_url(ACTOR_PATH),
+        "type": "Person",
+        "preferredUsername": options.username,
+        "name": config.title,
+        "summary": options.summary,
+        "url": config.absolute_url("/"),
+        "inbox": config.absolute_url("/inbox"),
+        "outbox": config.absolute_url(OUTBOX_PATH),
+    });
+    if let Some(icon) = &options.icon {
+        actor["icon"] = json!({
+            "type": "Image",
+            "url": config.absolute_url(icon),
+        });
+    }
+    actor
+}
+
+/// Every post, newest first, as an `Article` being created.
+pub fn outbox(posts: &[&PageContext]) -> Value {
+    let config = config();
+    let actor = config.absolute_url(ACTOR_PATH);
+    let items: Vec<Value> = posts
+        .iter()
+        .map(|post| {
+            let url = config.absolute_url(&post.url);
+            let published = post
+                .iso_date
+                .as_ref()
+                .map(|date| format!("{date}T00:00:00Z"));
+            json!({
+                "id": format!("{url}#create"),
+                "type": "Create",
+                "actor": actor,
+                "published": published,
+                "to": ["https://www.w3.org/ns/activitystreams#Public"],
+                "object": {
+                    "id": url,
+                    "type": "Article",
+                    "name": post.title,
+                    "content": post.contents,
+                    "url": url,
+                    "attributedTo": actor,
+                    "published": published,
+                    "to": ["https://www.w3.org/ns/activitystreams#Public"],
+                },
+            })
+        })
+        .collect();
+    json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": config.absolute_url(OUTBOX_PATH),
+        "type": "OrderedCollection",
+        "totalItems": items.len(),
+        "orderedItems": items,
+    })
+}
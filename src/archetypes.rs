@@ -0,0 +1,56 @@
+//! `blog new "My post"`: a new post, started from an archetype.
+//!
+//! An archetype is a template for a new post's markdown, in
+//! `templates/_archetypes/<type>.md`, or `templates/_archetypes/default.md`
+//! for any type. It gets the post's `title`, `slug`, `date`, and `type`.
+
+use std::fs;
+
+use anyhow::{bail, Result};
+use chrono::Local;
+use log::info;
+use tera::Tera;
+
+use crate::CONTENT_DIR;
+
+/// Used when there's no archetype for a post.
+const DEFAULT_ARCHETYPE: &str = r#"---
+title: {{ title | json_encode() }}
+{%- if type != "post" %}
+type: {{ type }}
+{%- endif %}
+draft: true
+---
+
+"#;
+
+/// Creates `content/<today>_<slug>.md` for a new post of type `page_type`.
+pub fn new_post(title: &str, page_type: &str) -> Result<()> {
+    let today = Local::now().date_naive();
+    let slug = slug::slugify(title);
+    let path = CONTENT_DIR.join(format!("{}_{slug}.md", today.format("%Y%m%d")));
+    if path.exists() {
+        bail!("{} already exists", path.to_string_lossy());
+    }
+
+    let mut context = tera::Context::new();
+    context.insert("title", title);
+    context.insert("slug", &slug);
+    context.insert("date", &today.to_string());
+    context.insert("type", page_type);
+    context.insert("build", &crate::site().build);
+
+    let archetype = [page_type, "default"]
+        .map(|name| format!("_archetypes/{name}.md"))
+        .into_iter()
+        .find(|template| crate::tera().get_template_names().any(|n| n == template));
+    let contents = match archetype {
+        Some(template) => crate::tera().render(&template, &context)?,
+        None => Tera::one_off(DEFAULT_ARCHETYPE, &context, false)?,
+    };
+
+    fs::create_dir_all(&*CONTENT_DIR)?;
+    fs::write(&path, contents)?;
+    info!("Writing {}", path.to_string_lossy());
+    Ok(())
+}
@@ -0,0 +1,67 @@
+//! The blogroll: feeds I follow, from `data/blogroll.toml`, as a page and as
+//! OPML for feed readers to import.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{config, html::escape};
+
+/// Where the blogroll page and its OPML end up.
+pub const URL: &str = "/blogroll/";
+pub const OPML_URL: &str = "/blogroll/opml.xml";
+
+/// A feed, under `[[feeds]]`.
+#[derive(Deserialize, Serialize)]
+pub struct Feed {
+    pub title: String,
+    /// The feed itself.
+    pub feed: String,
+    /// The site the feed is for.
+    pub url: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Blogroll {
+    pub feeds: Vec<Feed>,
+}
+
+impl Blogroll {
+    /// The blogroll in `data`, loaded from `data/blogroll.*`.
+    pub fn from_data(data: &tera::Value) -> Result<Blogroll> {
+        serde_json::from_value(data.clone()).context("cannot read data/blogroll")
+    }
+
+    pub fn opml(&self) -> String {
+        let mut outlines = String::new();
+        for feed in &self.feeds {
+            outlines.push_str(&format!(
+                "    <outline type=\"rss\" text=\"{title}\" title=\"{title}\" xmlUrl=\"{feed}\"",
+                title = escape(&feed.title),
+                feed = escape(&feed.feed),
+            ));
+            if let Some(url) = &feed.url {
+                outlines.push_str(&format!(" htmlUrl=\"{}\"", escape(url)));
+            }
+            if let Some(description) = &feed.description {
+                outlines.push_str(&format!(" description=\"{}\"", escape(description)));
+            }
+            outlines.push_str("/>\n");
+        }
+
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<opml version="2.0">
+  <head>
+    <title>{title}</title>
+    <ownerName>{author}</ownerName>
+  </head>
+  <body>
+{outlines}  </body>
+</opml>
+"#,
+            title = escape(&format!("{}: blogroll", config().title)),
+            author = escape(&config().author),
+        )
+    }
+}
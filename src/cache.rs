@@ -0,0 +1,141 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{checksum::Hasher, config, SYNTAX_DIR};
+
+/// On-disk cache of rendered page bodies, keyed by a hash of their markdown,
+/// the options it's rendered with, and what else goes into rendering it.
+///
+/// Markdown parsing and syntax highlighting are most of a build, and most
+/// posts don't change between builds.
+pub struct RenderCache {
+    dir: PathBuf,
+    /// Re-render everything, overwriting whatever was cached.
+    force: bool,
+    /// Hash of `syntaxes/` and the diagram commands, which change the html
+    /// without changing the markdown.
+    inputs: String,
+    used: HashSet<PathBuf>,
+}
+
+impl RenderCache {
+    pub fn new<P: AsRef<Path>>(dir: P, force: bool) -> RenderCache {
+        RenderCache {
+            dir: dir.as_ref().to_owned(),
+            force,
+            inputs: rendering_inputs(&SYNTAX_DIR, &config().diagrams),
+            used: HashSet::new(),
+        }
+    }
+
+    fn path_for<O: Serialize>(&self, markdown: &str, options: &O) -> PathBuf {
+        let mut hasher = Hasher::new();
+        // a new version of the generator may render things differently
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher.update(&self.inputs);
+        hasher.update(serde_json::to_string(options).unwrap_or_default());
+        hasher.update(markdown);
+        self.dir.join(hasher.finalize() + ".html")
+    }
+
+    /// Returns the cached html for `markdown`, or renders and caches it.
+    pub fn get_or_render<O: Serialize, F: FnOnce(&str) -> Result<String>>(
+        &mut self,
+        markdown: &str,
+        options: &O,
+        render: F,
+    ) -> Result<String> {
+        let path = self.path_for(markdown, options);
+
+        let cached = match self.force {
+            true => None,
+            false => fs::read_to_string(&path).ok(),
+        };
+        let html = match cached {
+            Some(html) => html,
+            None => {
+                let html = render(markdown)?;
+                fs::create_dir_all(&self.dir)?;
+                fs::write(&path, &html)?;
+                html
+            }
+        };
+
+        self.used.insert(path);
+        Ok(html)
+    }
+
+    /// Drops the cached html for `markdown`, e.g. because it didn't render
+    /// properly and should be tried again next time.
+    pub fn forget<O: Serialize>(&mut self, markdown: &str, options: &O) -> Result<()> {
+        let path = self.path_for(markdown, options);
+        self.used.remove(&path);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Removes entries that weren't used since this cache was created.
+    pub fn prune(&self) -> Result<()> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Ok(());
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.is_file() && !self.used.contains(&path) {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A hash of the syntax definitions in `syntax_dir` and the diagram commands,
+/// so changing either re-renders the pages they're used on.
+fn rendering_inputs(syntax_dir: &Path, diagrams: &HashMap<String, String>) -> String {
+    let mut hasher = Hasher::new();
+    let files = walkdir::WalkDir::new(syntax_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|entry| entry.file_type().is_file());
+    for entry in files {
+        hasher.update(entry.path().to_string_lossy().as_bytes());
+        hasher.update(fs::read(entry.path()).unwrap_or_default());
+    }
+    let diagrams: BTreeMap<_, _> = diagrams.iter().collect();
+    hasher.update(serde_json::to_string(&diagrams).unwrap_or_default());
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syntaxes_and_diagrams_change_the_key() {
+        crate::load_default_config();
+        let dir = std::env::temp_dir().join(format!("blog-syntaxes-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let diagrams = HashMap::from([("dot".to_owned(), "dot -Tsvg".to_owned())]);
+
+        let before = rendering_inputs(&dir, &diagrams);
+        assert_eq!(before, rendering_inputs(&dir, &diagrams));
+
+        fs::write(dir.join("Toy.sublime-syntax"), "name: Toy\n").unwrap();
+        let with_syntax = rendering_inputs(&dir, &diagrams);
+        assert_ne!(before, with_syntax);
+
+        let other = HashMap::from([("dot".to_owned(), "dot -Tsvg -Gdpi=300".to_owned())]);
+        assert_ne!(with_syntax, rendering_inputs(&dir, &other));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+//! Checksums of pages and files, to tell what changed between builds and
+//! deploys, with the algorithm set by `checksum` in config.toml.
+
+use sha2::{Digest, Sha256};
+
+use crate::{config, config::ChecksumAlgorithm};
+
+pub enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn new() -> Hasher {
+        match config().checksum {
+            ChecksumAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    pub fn update<C: AsRef<[u8]>>(&mut self, contents: C) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(contents),
+            Hasher::Blake3(hasher) => {
+                hasher.update(contents.as_ref());
+            }
+        }
+    }
+
+    /// The checksum, in hex.
+    pub fn finalize(self) -> String {
+        let hash = match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+        };
+        hash.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+/// A checksum of `contents`.
+pub fn checksum<C: AsRef<[u8]>>(contents: C) -> String {
+    let mut hasher = Hasher::new();
+    hasher.update(contents);
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The official test vectors' input: bytes counting up, mod 251.
+    fn input(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn blake3_hex(len: usize) -> String {
+        let mut hasher = Hasher::Blake3(Box::new(blake3::Hasher::new()));
+        hasher.update(input(len));
+        hasher.finalize()
+    }
+
+    #[test]
+    fn blake3_matches_test_vectors() {
+        // from BLAKE3's test_vectors.json, straddling the 1024-byte chunks
+        for (len, hash) in [
+            (
+                0,
+                "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+            ),
+            (
+                1,
+                "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213",
+            ),
+            (
+                1023,
+                "10108970eeda3eb932baac1428c7a2163b0e924c9a9e25b35bba72b28f70bd11",
+            ),
+            (
+                1024,
+                "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7",
+            ),
+            (
+                1025,
+                "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444",
+            ),
+        ] {
+            assert_eq!(blake3_hex(len), hash, "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn sha256_matches_test_vector() {
+        let mut hasher = Hasher::Sha256(Sha256::new());
+        hasher.update("abc");
+        assert_eq!(
+            hasher.finalize(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+
+#[derive(Default, PartialEq)]
+pub enum Command {
+    /// Build the site (the default).
+    #[default]
+    Build,
+    /// Remove everything previous builds wrote, and forget about them.
+    Clean,
+    /// Check that links to other sites in the built pages still work.
+    CheckLinks,
+    /// Check that the site is set up properly.
+    Doctor,
+    /// Summarize what's in the site.
+    Stats,
+    /// Create a new site in a directory.
+    Init(PathBuf),
+    /// Start a new post with this title.
+    New(String),
+    /// Import pages from another generator's site, given its name and
+    /// directory.
+    Import(String, PathBuf),
+    /// Export posts in a format, like `epub`.
+    Export(String),
+    /// Build the site, then upload it somewhere, like `s3`.
+    Deploy(String),
+}
+
+/// Command line options.
+#[derive(Default)]
+pub struct Args {
+    pub command: Command,
+    /// Only print warnings and errors.
+    pub quiet: bool,
+    /// Also print what was skipped, and why. Wins over `quiet`.
+    pub verbose: bool,
+    /// Ignore anything cached from previous builds and render everything.
+    pub force: bool,
+    /// Include posts dated in the future.
+    pub future: bool,
+    /// Fail the build if there are any warnings.
+    pub strict: bool,
+    /// Report how long each part of the build took.
+    pub timings: bool,
+    /// Only export posts from this year.
+    pub year: Option<i32>,
+    /// Only export posts with this tag.
+    pub tag: Option<String>,
+    /// The type of post `new` starts.
+    pub page_type: Option<String>,
+    /// Delete files in `content/` that no page uses, once they're confirmed.
+    pub delete_orphans: bool,
+    /// Don't ask before deleting anything.
+    pub yes: bool,
+    /// Lay `config.<env>.toml` over `config.toml`, e.g. `dev` or `prod`.
+    pub env: Option<String>,
+    /// After building, send webmentions for the links in new and changed
+    /// pages.
+    pub send_webmentions: bool,
+}
+
+impl Args {
+    pub fn parse() -> Result<Args> {
+        let mut parsed = Args::default();
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--force" => parsed.force = true,
+                "--future" => parsed.future = true,
+                "--delete-orphans" => parsed.delete_orphans = true,
+                "--yes" | "-y" => parsed.yes = true,
+                "--send-webmentions" => parsed.send_webmentions = true,
+                "--strict" => parsed.strict = true,
+                "--timings" => parsed.timings = true,
+                "--quiet" | "-q" => parsed.quiet = true,
+                "--verbose" | "-v" => parsed.verbose = true,
+                "build" => parsed.command = Command::Build,
+                "clean" => parsed.command = Command::Clean,
+                "check-links" => parsed.command = Command::CheckLinks,
+                "doctor" => parsed.command = Command::Doctor,
+                "stats" => parsed.command = Command::Stats,
+                "--year" => {
+                    let year = args.next().context("`--year` needs a year")?;
+                    parsed.year = Some(year.parse().context("`--year` needs a year")?);
+                }
+                "--env" => parsed.env = Some(args.next().context("`--env` needs a name")?),
+                "--tag" => parsed.tag = Some(args.next().context("`--tag` needs a tag")?),
+                "--type" => {
+                    parsed.page_type = Some(args.next().context("`--type` needs a type")?);
+                }
+                "new" => {
+                    let title = args.next().context("`new` needs a title")?;
+                    parsed.command = Command::New(title);
+                }
+                "export" => {
+                    let format = args.next().context("`export` needs a format")?;
+                    parsed.command = Command::Export(format);
+                }
+                "deploy" => {
+                    let target = args.next().context("`deploy` needs a target")?;
+                    parsed.command = Command::Deploy(target);
+                }
+                "import" => {
+                    let generator = args.next().context("`import` needs a generator")?;
+                    let dir = args.next().context("`import` needs a directory")?;
+                    parsed.command = Command::Import(generator, dir.into());
+                }
+                "init" => {
+                    let dir = args.next().context("`init` needs a directory")?;
+                    parsed.command = Command::Init(dir.into());
+                }
+                _ => bail!("unknown argument `{arg}`"),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
@@ -0,0 +1,166 @@
+//! Gzipped and brotli copies of text files in the output directory, for
+//! static hosts that serve `index.html.gz` or `index.html.br` instead of
+//! compressing `index.html` on the fly.
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use brotli::CompressorWriter;
+use flate2::{write::GzEncoder, Compression};
+use log::debug;
+use walkdir::WalkDir;
+
+use crate::{checksum, staging::write_atomically, state::State, WEBSITE_DIR};
+
+/// Files with these extensions get compressed copies next to them.
+const EXTENSIONS: [&str; 4] = ["html", "css", "js", "svg"];
+
+/// Whether `path` is a `.gz` or `.br` copy of a text file next to it.
+pub fn is_compressed_copy(path: &Path) -> bool {
+    let is_compressed = path.extension().is_some_and(|e| e == "gz" || e == "br");
+    let original = path.with_extension("");
+    let extension = original
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    is_compressed && EXTENSIONS.contains(&extension) && original.is_file()
+}
+
+/// Writes a `.gz` and a `.br` next to every text file in the output
+/// directory, whether a build wrote it or not. Files that haven't changed
+/// since they were last compressed are left alone, and `.gz`s of files that
+/// are gone are removed.
+pub fn precompress(state: &mut State) -> Result<()> {
+    let mut files = HashSet::new();
+    for entry in WalkDir::new(&*WEBSITE_DIR) {
+        let path = entry?.into_path();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        if path.is_file() && EXTENSIONS.contains(&extension) {
+            files.insert(path);
+        }
+    }
+
+    for path in state
+        .compressed
+        .keys()
+        .filter(|path| !files.contains(*path))
+        .cloned()
+        .collect::<Vec<_>>()
+    {
+        state.compressed.remove(&path);
+        for compressed_path in compressed_paths(&path) {
+            if compressed_path.is_file() {
+                fs::remove_file(&compressed_path)?;
+            }
+            state.outputs.remove(&compressed_path);
+        }
+    }
+
+    for path in files {
+        let contents = fs::read(&path)?;
+        let checksum = checksum::checksum(&contents);
+        let [gz_path, br_path] = compressed_paths(&path);
+        if state.compressed.get(&path) == Some(&checksum) && gz_path.is_file() && br_path.is_file()
+        {
+            continue;
+        }
+        debug!("Compressing {}", path.to_string_lossy());
+        let [gz, br] = compress(&contents)?;
+        write_atomically(&gz_path, &gz)?;
+        write_atomically(&br_path, &br)?;
+
+        state.compressed.insert(path, checksum);
+        state.outputs.insert(gz_path);
+        state.outputs.insert(br_path);
+    }
+
+    Ok(())
+}
+
+/// `contents` gzipped and brotli compressed, as well as they can be.
+fn compress(contents: &[u8]) -> Result<[Vec<u8>; 2]> {
+    let mut gz = GzEncoder::new(Vec::new(), Compression::best());
+    gz.write_all(contents)?;
+    // with the largest window brotli has
+    let mut br = CompressorWriter::new(Vec::new(), 4096, 11, 24);
+    br.write_all(contents)?;
+    Ok([gz.finish()?, br.into_inner()])
+}
+
+/// Where the `.gz` and `.br` of `path` go.
+fn compressed_paths(path: &Path) -> [PathBuf; 2] {
+    [".gz", ".br"].map(|extension| {
+        let mut compressed_path = path.as_os_str().to_owned();
+        compressed_path.push(extension);
+        compressed_path.into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use brotli::Decompressor;
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    #[test]
+    fn compressed_copies_decompress() {
+        let contents = "<p>hello</p>\n".repeat(100);
+        let [gz, br] = compress(contents.as_bytes()).unwrap();
+        assert!(gz.len() < contents.len() && br.len() < contents.len());
+
+        let mut gunzipped = String::new();
+        GzDecoder::new(&gz[..])
+            .read_to_string(&mut gunzipped)
+            .unwrap();
+        assert_eq!(gunzipped, contents);
+        let mut unbrotlied = String::new();
+        Decompressor::new(&br[..], 4096)
+            .read_to_string(&mut unbrotlied)
+            .unwrap();
+        assert_eq!(unbrotlied, contents);
+    }
+
+    #[test]
+    fn compressed_copies_go_next_to_the_file() {
+        assert_eq!(
+            compressed_paths(Path::new("website/index.html")),
+            [
+                PathBuf::from("website/index.html.gz"),
+                PathBuf::from("website/index.html.br")
+            ]
+        );
+    }
+
+    #[test]
+    fn tells_compressed_copies_apart() {
+        let dir = std::env::temp_dir().join(format!("blog-compressed-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "index.html",
+            "index.html.gz",
+            "index.html.br",
+            "data.gz",
+            "gone.css.br",
+        ] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        assert!(is_compressed_copy(&dir.join("index.html.gz")));
+        assert!(is_compressed_copy(&dir.join("index.html.br")));
+        assert!(!is_compressed_copy(&dir.join("index.html")));
+        assert!(!is_compressed_copy(&dir.join("data.gz")));
+        // without the original, it's a file of its own
+        assert!(!is_compressed_copy(&dir.join("gone.css.br")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,623 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::NaiveDate;
+use gray_matter::{
+    engine::{Engine, YAML},
+    Pod,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::CONTENT_DIR;
+
+/// A directory pages are read from, in `content_dirs`: just its path, or
+/// `{ path = "notes", prefix = "/notes/" }` to put its pages' urls under
+/// `prefix`.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum ContentDir {
+    Path(PathBuf),
+    Prefixed { path: PathBuf, prefix: String },
+}
+
+impl ContentDir {
+    pub fn path(&self) -> &Path {
+        match self {
+            ContentDir::Path(path) | ContentDir::Prefixed { path, .. } => path,
+        }
+    }
+
+    /// `url`, a page's url within this directory, under its prefix.
+    pub fn prefixed(&self, url: &str) -> String {
+        match self {
+            ContentDir::Path(_) => url.to_owned(),
+            ContentDir::Prefixed { prefix, .. } => {
+                format!("/{}{url}", prefix.trim_matches('/')).replace("//", "/")
+            }
+        }
+    }
+}
+
+/// How the index is ordered. Pinned pages always come first.
+#[derive(Deserialize, Serialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SortBy {
+    /// Newest first.
+    #[default]
+    Date,
+    /// Oldest first.
+    DateAsc,
+    Title,
+    /// Lightest first.
+    Weight,
+}
+
+/// Where a Content-Security-Policy for each page goes, if anywhere.
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CspMode {
+    #[default]
+    Off,
+    /// A `<meta http-equiv>` in the page's `<head>`.
+    Meta,
+    /// An entry for the page in `_headers`.
+    Headers,
+}
+
+/// How files are hashed to tell whether they changed.
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumAlgorithm {
+    #[default]
+    Sha256,
+    /// Several times faster, but changing to it makes every file look
+    /// changed once.
+    Blake3,
+}
+
+/// How a build tells whether a file it copies has changed since the last.
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeDetection {
+    /// By its checksum, which means reading every file.
+    #[default]
+    Checksum,
+    /// By its size and modification time, falling back to its checksum when
+    /// either differs.
+    Metadata,
+}
+
+/// What switches highlighting to its dark theme.
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DarkMode {
+    /// The reader's `prefers-color-scheme`.
+    #[default]
+    Media,
+    /// A `data-theme="dark"` on the page, e.g. set by a toggle.
+    DataTheme,
+}
+
+/// Settings for a `type` of page, under `[types.<name>]`.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TypeConfig {
+    /// Defaults to `<name>.html` if there is one, otherwise `page.html`.
+    pub template: Option<String>,
+    /// Whether pages of this type are listed in the index.
+    pub in_index: bool,
+}
+
+impl Default for TypeConfig {
+    fn default() -> Self {
+        TypeConfig {
+            template: None,
+            in_index: true,
+        }
+    }
+}
+
+/// Someone who writes posts, under `[authors.<id>]`.
+#[derive(Deserialize, Serialize, Default, Clone)]
+#[serde(default)]
+pub struct AuthorConfig {
+    pub name: String,
+    /// Their own website.
+    pub url: Option<String>,
+    pub avatar: Option<String>,
+}
+
+/// Rules for pages' front matter, under `[front_matter]`.
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct FrontMatterConfig {
+    /// Fail the build on front matter problems, rather than warning.
+    pub strict: bool,
+    /// Keys every page has to have (defaults count).
+    pub required: Vec<String>,
+    /// Keys allowed besides the built-in ones. If unset, anything goes.
+    pub allowed_extra: Option<Vec<String>>,
+}
+
+/// Markdown extensions, under `[markdown]`. Pages can change them for
+/// themselves with `markdown:` in their front matter.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct MarkdownConfig {
+    pub footnotes: bool,
+    pub tables: bool,
+    pub strikethrough: bool,
+    /// Turn every line break into a `<br>`, e.g. for poetry.
+    pub hard_line_breaks: bool,
+    /// Let html in markdown through as it is.
+    pub allow_dangerous_html: bool,
+    /// Render `$...$` and `$$...$$` as math.
+    pub math: bool,
+    /// Curly quotes, em dashes, and ellipses.
+    pub smart_punctuation: bool,
+}
+
+impl Default for MarkdownConfig {
+    fn default() -> Self {
+        MarkdownConfig {
+            footnotes: true,
+            tables: true,
+            strikethrough: true,
+            hard_line_breaks: false,
+            allow_dangerous_html: true,
+            math: false,
+            smart_punctuation: false,
+        }
+    }
+}
+
+/// Checks for accessibility problems in pages, under `[accessibility]`.
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct AccessibilityConfig {
+    pub check: bool,
+    /// Fail the build on problems, rather than warning.
+    pub strict: bool,
+}
+
+impl Default for AccessibilityConfig {
+    fn default() -> Self {
+        AccessibilityConfig {
+            check: true,
+            strict: false,
+        }
+    }
+}
+
+/// What to do with links to other sites, under `[external_links]`.
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct ExternalLinksConfig {
+    /// Empty to leave `rel` alone.
+    pub rel: String,
+    /// Open them in a new tab.
+    pub new_tab: bool,
+    /// A class to mark them with, e.g. for an icon.
+    pub class: Option<String>,
+}
+
+impl ExternalLinksConfig {
+    /// Whether links to other sites are changed at all.
+    pub fn is_on(&self) -> bool {
+        !self.rel.is_empty() || self.new_tab || self.class.is_some()
+    }
+}
+
+/// Publishing pages' markdown next to them, under `[source]`.
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct SourceConfig {
+    /// Copy each page's markdown to an `index.md` next to its `index.html`.
+    pub publish: bool,
+    /// Keep the front matter in the copy.
+    pub front_matter: bool,
+}
+
+impl Default for SourceConfig {
+    fn default() -> Self {
+        SourceConfig {
+            publish: false,
+            front_matter: true,
+        }
+    }
+}
+
+/// Syntax highlighting, written out as `syntax.css`, under `[highlighting]`.
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct HighlightingConfig {
+    /// A `.tmTheme` in `themes/`, by name, e.g. `gruvbox (Light) (Hard)`.
+    pub theme: Option<String>,
+    /// Another for dark mode.
+    pub dark_theme: Option<String>,
+    pub dark_mode: DarkMode,
+}
+
+/// Shell commands run during builds, under `[hooks]`. Each runs in the
+/// site's directory with `BLOG_OUTPUT_DIR` set, and fails the build if it
+/// fails.
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Before anything's read.
+    pub pre_build: Option<String>,
+    /// After everything's written, once for each page whose body changed,
+    /// with `BLOG_SLUG`, `BLOG_URL`, and `BLOG_PAGE_DIR` set.
+    pub post_page: Option<String>,
+    /// After everything's written, with the slugs of the pages whose bodies
+    /// changed in `BLOG_CHANGED`, one per line.
+    pub post_build: Option<String>,
+}
+
+/// A static ActivityPub actor for the site, under `[activitypub]`.
+#[derive(Deserialize, Serialize)]
+pub struct ActivityPubConfig {
+    /// The actor is `@<username>@<host>`.
+    pub username: String,
+    #[serde(default)]
+    pub summary: String,
+    /// A path to an avatar, e.g. `/favicon.svg`.
+    pub icon: Option<String>,
+}
+
+/// Favicons made from one image, under `[favicon]`.
+#[derive(Deserialize, Serialize)]
+pub struct FaviconConfig {
+    /// A square svg, or a png, jpeg, or webp, ideally at least 512px, e.g.
+    /// `static/favicon.png`.
+    pub source: String,
+    /// What's behind the apple touch and maskable icons, which can't be
+    /// transparent.
+    #[serde(default = "default_favicon_background")]
+    pub background: String,
+}
+
+fn default_favicon_background() -> String {
+    "#ffffff".to_owned()
+}
+
+/// Where `blog deploy s3` uploads to, under `[s3]`.
+#[derive(Deserialize, Serialize)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    /// A directory in the bucket to upload to, if not the top.
+    #[serde(default)]
+    pub prefix: String,
+    /// Invalidate changed files in this CloudFront distribution.
+    pub cloudfront_distribution: Option<String>,
+    /// `Cache-Control` headers by file extension, e.g. `html = "max-age=300"`.
+    #[serde(default)]
+    pub cache_control: HashMap<String, String>,
+    /// `Cache-Control` for everything else.
+    #[serde(default = "default_cache_control")]
+    pub default_cache_control: String,
+}
+
+fn default_cache_control() -> String {
+    "max-age=3600".to_owned()
+}
+
+/// Site-wide settings, read from `config.toml`. Exposed to every template as
+/// `site`.
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub title: String,
+    pub base_url: String,
+    pub author: String,
+    /// The language pages are written in, unless they say otherwise.
+    pub language: String,
+    /// The default format for the `date` filter, e.g. `%-d %B %Y`.
+    pub date_format: String,
+    /// The locale for month and day names in the `date` filter, e.g. `ko_KR`.
+    pub locale: String,
+    /// Where pages are read from, merged into one site. `content/` alone by
+    /// default; new posts and `.well-known` files always go there.
+    pub content_dirs: Vec<ContentDir>,
+    /// A theme in `themes/<name>/`, with `templates/`, `static/` files for
+    /// the output directory, and a `syntax.tmTheme`. The site's own files
+    /// override the theme's.
+    pub theme: Option<String>,
+    /// Where pages end up, e.g. `/{year}/{slug}/`. Can use `{slug}`, and
+    /// `{year}`, `{month}`, `{day}` for pages with a date.
+    pub permalink: String,
+    pub sort_by: SortBy,
+    pub types: HashMap<String, TypeConfig>,
+    pub authors: HashMap<String, AuthorConfig>,
+    /// Front matter every page starts with, e.g. `author = "kevin"`.
+    pub defaults: serde_json::Map<String, serde_json::Value>,
+    pub markdown: MarkdownConfig,
+    /// Elements whose last two words are kept on the same line, so they
+    /// don't end with a word on its own, e.g. `["h1", "h2", "p"]`.
+    pub prevent_widows: Vec<String>,
+    pub external_links: ExternalLinksConfig,
+    /// Fail the build on links to pages or files that don't exist, rather
+    /// than warning.
+    pub strict_links: bool,
+    pub accessibility: AccessibilityConfig,
+    /// Check finished pages for html errors, like unclosed tags.
+    pub validate_html: bool,
+    /// Mark up pages with microformats, like `h-entry` and `p-author`, for
+    /// the IndieWeb.
+    pub microformats: bool,
+    /// Add `integrity` hashes to the site's own scripts and stylesheets.
+    pub subresource_integrity: bool,
+    /// Work out a Content-Security-Policy for each page from what it loads,
+    /// with hashes of its inline scripts and styles.
+    pub csp: CspMode,
+    pub checksum: ChecksumAlgorithm,
+    /// Read through symlinked directories in `content/`, e.g. assets shared
+    /// between sites. Without it, they're skipped.
+    pub follow_symlinks: bool,
+    pub change_detection: ChangeDetection,
+    /// Fail the build if there are any warnings, e.g. in CI.
+    pub strict: bool,
+    /// Commands that turn code blocks into svg diagrams, by language. Each
+    /// gets the code on stdin, and writes svg to stdout.
+    pub diagrams: HashMap<String, String>,
+    pub front_matter: FrontMatterConfig,
+    /// Delete pages past their `expires` date, and their alias redirects,
+    /// rather than just leaving them out of the index.
+    pub delete_expired: bool,
+    /// Write a `_redirects` file for page aliases.
+    pub redirects_file: bool,
+    /// Write a `CNAME` file with this domain, for GitHub Pages.
+    pub cname: Option<String>,
+    /// Write a `.nojekyll` file, so GitHub Pages serves the site as it is.
+    pub nojekyll: bool,
+    /// Write a gzipped `.gz` and a brotli `.br` next to each html, css, js,
+    /// and svg file, for hosts that serve precompressed files.
+    pub precompress: bool,
+    pub s3: Option<S3Config>,
+    pub favicon: Option<FaviconConfig>,
+    /// Write each page's metadata and contents to an `index.json` next to
+    /// its `index.html`, and every page's metadata to `pages.json`.
+    pub json_output: bool,
+    /// Write each page as wrapped plain text to an `index.txt` next to its
+    /// `index.html`.
+    pub text_output: bool,
+    /// Also build a Gemini capsule in `capsule/`, with each page as gemtext.
+    pub gemini: bool,
+    /// Write an Atom feed of each tag's posts to `/tags/<tag>/feed.xml`.
+    pub tag_feeds: bool,
+    /// Take each page's `updated` time from the last commit to its source
+    /// file, since checkouts don't keep file times.
+    pub git_dates: bool,
+    pub source: SourceConfig,
+    pub highlighting: HighlightingConfig,
+    pub hooks: HooksConfig,
+    pub activitypub: Option<ActivityPubConfig>,
+    /// Files to write to `/.well-known/`, by name, e.g.
+    /// `[well_known]` `"security.txt" = "..."`.
+    pub well_known: BTreeMap<String, String>,
+    /// Headers to write to a `_headers` file, by url pattern, e.g.
+    /// `[headers."/*"]`.
+    pub headers: BTreeMap<String, BTreeMap<String, String>>,
+    /// Anything else templates might want, e.g. `site.extra.twitter`.
+    pub extra: HashMap<String, toml::Value>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            title: String::new(),
+            base_url: String::new(),
+            author: String::new(),
+            language: "en".to_owned(),
+            date_format: "%Y-%m-%d".to_owned(),
+            locale: "en_US".to_owned(),
+            content_dirs: vec![ContentDir::Path(CONTENT_DIR.clone())],
+            theme: None,
+            permalink: "/{slug}/".to_owned(),
+            sort_by: SortBy::default(),
+            types: HashMap::new(),
+            authors: HashMap::new(),
+            defaults: serde_json::Map::new(),
+            markdown: MarkdownConfig::default(),
+            prevent_widows: Vec::new(),
+            external_links: ExternalLinksConfig::default(),
+            strict_links: false,
+            accessibility: AccessibilityConfig::default(),
+            validate_html: false,
+            microformats: false,
+            subresource_integrity: false,
+            csp: CspMode::default(),
+            checksum: ChecksumAlgorithm::default(),
+            follow_symlinks: false,
+            change_detection: ChangeDetection::default(),
+            strict: false,
+            diagrams: HashMap::from([
+                (
+                    "mermaid".to_owned(),
+                    "mmdc --input - --output - --outputFormat svg --quiet".to_owned(),
+                ),
+                ("dot".to_owned(), "dot -Tsvg".to_owned()),
+            ]),
+            front_matter: FrontMatterConfig::default(),
+            delete_expired: false,
+            redirects_file: false,
+            cname: None,
+            nojekyll: false,
+            precompress: false,
+            s3: None,
+            favicon: None,
+            json_output: false,
+            text_output: false,
+            gemini: false,
+            tag_feeds: false,
+            git_dates: false,
+            source: SourceConfig::default(),
+            highlighting: HighlightingConfig::default(),
+            hooks: HooksConfig::default(),
+            activitypub: None,
+            well_known: BTreeMap::new(),
+            headers: BTreeMap::new(),
+            extra: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config at `path`, e.g. `config.toml`, with `config.<env>.toml`
+    /// next to it laid over it if there's an `env`. Either can be YAML or
+    /// JSON instead, as `.yaml`, `.yml`, or `.json`.
+    pub fn from_file<P: AsRef<Path>>(path: P, env: Option<&str>) -> Result<Config> {
+        let path = find_file(path.as_ref());
+        let mut config = read_file(&path)?;
+        let mut name = path.to_string_lossy().into_owned();
+        if let Some(env) = env {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let overlay_path = find_file(&path.with_file_name(format!("{stem}.{env}.toml")));
+            overlay(&mut config, read_file(&overlay_path)?);
+            name = format!("{name} with {}", overlay_path.to_string_lossy());
+        }
+        let config = config
+            .try_into()
+            .with_context(|| format!("cannot parse {name}"))?;
+        Ok(config)
+    }
+
+    /// Fills in the `permalink` pattern for a page. The result always starts
+    /// and ends with a `/`.
+    pub fn permalink(&self, slug: &str, date: Option<NaiveDate>) -> Result<String> {
+        let mut url = self.permalink.replace("{slug}", slug);
+        if let Some(date) = date {
+            url = url
+                .replace("{year}", &date.format("%Y").to_string())
+                .replace("{month}", &date.format("%m").to_string())
+                .replace("{day}", &date.format("%d").to_string());
+        }
+        if url.contains('{') {
+            bail!("cannot fill in permalink `{}` for `{slug}`", self.permalink);
+        }
+
+        Ok(format!("/{}/", url.trim_matches('/')).replace("//", "/"))
+    }
+
+    /// Whether `url` is on another site.
+    pub fn is_external(&self, url: &str) -> bool {
+        let host = |url: &str| {
+            let rest = url.split_once("//").map_or("", |p| p.1);
+            rest.split(['/', '?', '#'])
+                .next()
+                .unwrap_or_default()
+                .to_lowercase()
+        };
+        let is_absolute =
+            url.starts_with("http://") || url.starts_with("https://") || url.starts_with("//");
+        is_absolute && host(url) != host(&self.base_url)
+    }
+
+    /// Turns a site path like `/about/` into a full url under `base_url`.
+    pub fn absolute_url(&self, path: &str) -> String {
+        format!(
+            "{}/{}",
+            self.base_url.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
+
+    /// Turns a site path like `/about/` into one from the root of the host,
+    /// under whatever path `base_url` has, e.g. `/blog/about/`.
+    pub fn root_relative_url(&self, path: &str) -> String {
+        let base_path = &self.base_url[self.origin().len()..];
+        format!(
+            "{}/{}",
+            base_path.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
+
+    /// `base_url` without any path, e.g. `https://example.com`, which
+    /// root-relative urls are relative to.
+    pub fn origin(&self) -> &str {
+        let host_start = self.base_url.find("//").map_or(0, |i| i + 2);
+        match self.base_url[host_start..].find('/') {
+            Some(i) => &self.base_url[..host_start + i],
+            None => &self.base_url,
+        }
+    }
+}
+
+/// `path`, a `.toml` file, or if there isn't one, a YAML or JSON file with
+/// the same name.
+pub fn find_file(path: &Path) -> PathBuf {
+    if path.exists() {
+        return path.to_owned();
+    }
+    ["yaml", "yml", "json"]
+        .iter()
+        .map(|extension| path.with_extension(extension))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| path.to_owned())
+}
+
+/// Reads a TOML, YAML, or JSON file, going by its extension.
+fn read_file(path: &Path) -> Result<toml::Value> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("cannot read {}", path.to_string_lossy()))?;
+    let cannot_parse = || format!("cannot parse {}", path.to_string_lossy());
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    match extension.as_ref() {
+        "json" => serde_json::from_str(&contents).with_context(cannot_parse),
+        "yaml" | "yml" => match YAML::parse(&contents) {
+            // the yaml engine doesn't report errors, it just gives up
+            Pod::Null if !contents.trim().is_empty() => Err(anyhow!(cannot_parse())),
+            pod => pod.deserialize().with_context(cannot_parse),
+        },
+        _ => toml::from_str(&contents).with_context(cannot_parse),
+    }
+}
+
+/// Lays `overlay` over `base`: tables are merged, key by key, and anything
+/// else in `overlay` replaces what's in `base`.
+fn overlay(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => self::overlay(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_keep_the_base_path() {
+        let mut config = Config {
+            base_url: "https://example.com/blog/".to_owned(),
+            ..Config::default()
+        };
+        assert_eq!(config.root_relative_url("/about/"), "/blog/about/");
+        assert_eq!(config.root_relative_url(""), "/blog/");
+        assert_eq!(
+            config.absolute_url("style.css"),
+            "https://example.com/blog/style.css"
+        );
+
+        assert_eq!(config.origin(), "https://example.com");
+
+        config.base_url = "https://example.com".to_owned();
+        assert_eq!(config.root_relative_url("/about/"), "/about/");
+        assert_eq!(config.root_relative_url(""), "/");
+    }
+}
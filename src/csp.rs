@@ -0,0 +1,208 @@
+//! Content-Security-Policies worked out from rendered pages: the site itself,
+//! the other sites they load things from, and hashes of their inline scripts,
+//! styles, and event handlers.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use kuchikiki::{traits::TendrilSink, NodeRef};
+use sha2::{Digest, Sha256};
+
+use crate::config;
+
+/// Elements that load things, by the directive that covers them.
+const SOURCES: [(&str, &str, &str); 7] = [
+    ("script-src", "script[src]", "src"),
+    ("style-src", "link[rel~=stylesheet][href]", "href"),
+    ("img-src", "img[src]", "src"),
+    ("img-src", "source[srcset]", "srcset"),
+    ("media-src", "video[src], audio[src], source[src]", "src"),
+    ("frame-src", "iframe[src]", "src"),
+    ("font-src", "link[rel~=preload][as=font][href]", "href"),
+];
+
+/// The policy for `document`, a rendered page.
+pub fn policy(document: &NodeRef) -> String {
+    let mut directives: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+    for (directive, selector, attribute) in SOURCES {
+        for element in document.select(selector).unwrap() {
+            let attributes = element.attributes.borrow();
+            let value = attributes.get(attribute).unwrap_or_default();
+            // srcsets are urls, each followed by a size
+            let urls = value.split(',').filter_map(|c| c.split_whitespace().next());
+            for url in urls {
+                directives.entry(directive).or_default().insert(source(url));
+            }
+        }
+    }
+
+    // click-to-load embeds, like the `youtube` shortcode's, make an iframe
+    // of their `data-embed` when clicked
+    for element in document.select("[data-embed]").unwrap() {
+        let attributes = element.attributes.borrow();
+        let url = attributes.get("data-embed").unwrap_or_default();
+        directives
+            .entry("frame-src")
+            .or_default()
+            .insert(source(url));
+    }
+
+    for (directive, selector) in [("script-src", "script:not([src])"), ("style-src", "style")] {
+        for element in document.select(selector).unwrap() {
+            // data blocks, like json-ld, aren't run
+            let attributes = element.attributes.borrow();
+            let kind = attributes.get("type").unwrap_or_default();
+            if directive == "script-src" && !matches!(kind, "" | "module" | "text/javascript") {
+                continue;
+            }
+            let text = element.as_node().text_contents();
+            if !text.trim().is_empty() {
+                directives.entry(directive).or_default().insert(hash(&text));
+            }
+        }
+    }
+
+    // `onclick` and the like, like `style` attributes, only work with
+    // `'unsafe-hashes'`
+    let mut handlers = false;
+    for element in document.select("*").unwrap() {
+        let attributes = element.attributes.borrow();
+        for (name, attribute) in &attributes.map {
+            if name.local.starts_with("on") {
+                directives
+                    .entry("script-src")
+                    .or_default()
+                    .insert(hash(&attribute.value));
+                handlers = true;
+            }
+        }
+    }
+    if handlers {
+        directives
+            .entry("script-src")
+            .or_default()
+            .insert("'unsafe-hashes'".to_owned());
+    }
+
+    // `style` attributes only work with `'unsafe-hashes'`
+    let mut style_attributes = false;
+    for element in document.select("[style]").unwrap() {
+        let style = element
+            .attributes
+            .borrow()
+            .get("style")
+            .unwrap_or_default()
+            .to_owned();
+        directives
+            .entry("style-src")
+            .or_default()
+            .insert(hash(&style));
+        style_attributes = true;
+    }
+    if style_attributes {
+        directives
+            .entry("style-src")
+            .or_default()
+            .insert("'unsafe-hashes'".to_owned());
+    }
+
+    let mut policy = vec!["default-src 'self'".to_owned()];
+    for (directive, mut sources) in directives {
+        sources.insert("'self'".to_owned());
+        let sources: Vec<String> = sources.into_iter().collect();
+        policy.push(format!("{directive} {}", sources.join(" ")));
+    }
+    policy.push("base-uri 'self'".to_owned());
+    policy.push("form-action 'self'".to_owned());
+    policy.join("; ")
+}
+
+/// Adds `policy` as the first thing in `document`'s `<head>`, so it covers
+/// everything after it.
+pub fn add_meta(document: &NodeRef, policy: &str) {
+    let Ok(head) = document.select_first("head") else {
+        return;
+    };
+    let meta = format!(
+        r#"<meta http-equiv="Content-Security-Policy" content="{}">"#,
+        policy.replace('&', "&amp;").replace('"', "&quot;")
+    );
+    let meta_document = kuchikiki::parse_html().one(meta);
+    if let Ok(meta) = meta_document.select_first("meta") {
+        head.as_node().prepend(meta.as_node().clone());
+    }
+}
+
+/// The source a url is from: `'self'` for this site, the scheme for `data:`
+/// urls, and otherwise its origin.
+fn source(url: &str) -> String {
+    if let Some((scheme, _)) = url
+        .split_once(':')
+        .filter(|(s, _)| *s == "data" || *s == "blob")
+    {
+        return format!("{scheme}:");
+    }
+    if !config().is_external(url) {
+        return "'self'".to_owned();
+    }
+    let (scheme, rest) = url.split_once("//").unwrap_or(("https:", url));
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    match scheme {
+        "" => format!("https://{host}"),
+        scheme => format!("{scheme}//{host}"),
+    }
+}
+
+fn hash(text: &str) -> String {
+    format!("'sha256-{}'", BASE64_STANDARD.encode(Sha256::digest(text)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{load_default_config, shortcodes};
+
+    /// The sources `policy` has for `directive`.
+    fn sources<'a>(policy: &'a str, directive: &str) -> Vec<&'a str> {
+        policy
+            .split("; ")
+            .find_map(|d| d.strip_prefix(directive)?.strip_prefix(' '))
+            .map_or(Vec::new(), |sources| sources.split(' ').collect())
+    }
+
+    fn policy_for(html: &str) -> String {
+        load_default_config();
+        policy(&kuchikiki::parse_html().one(html))
+    }
+
+    #[test]
+    fn allows_click_to_load_videos() {
+        let markdown = r#"{{ youtube(id="dQw4w9WgXcQ") }}"#;
+        let tera = tera::Tera::default();
+        let rendered = shortcodes::render_shortcodes(markdown, &tera, &tera::Context::new());
+        let html = rendered.ok().unwrap().html.remove(0);
+        let policy = policy_for(&html);
+
+        let document = kuchikiki::parse_html().one(html);
+        let link = document.select_first("[onclick]").unwrap();
+        let onclick = link.attributes.borrow().get("onclick").unwrap().to_owned();
+        let script_src = sources(&policy, "script-src");
+        assert!(script_src.contains(&hash(&onclick).as_str()), "{policy}");
+        assert!(script_src.contains(&"'unsafe-hashes'"), "{policy}");
+        let frame_src = sources(&policy, "frame-src");
+        assert!(
+            frame_src.contains(&"https://www.youtube-nocookie.com"),
+            "{policy}"
+        );
+    }
+
+    #[test]
+    fn hashes_inline_scripts_but_not_data() {
+        let policy =
+            policy_for(r#"<script>run()</script><script type="application/ld+json">{}</script>"#);
+        assert_eq!(
+            sources(&policy, "script-src"),
+            ["'self'", hash("run()").as_str()]
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use gray_matter::{
+    engine::{Engine, YAML},
+    Pod,
+};
+use tera::Value;
+use walkdir::WalkDir;
+
+/// Reads every TOML/YAML/JSON file directly inside `dir`, keyed by file stem,
+/// so that `data/links.toml` ends up as `data.links` in templates.
+///
+/// A missing directory just means there's no data.
+pub fn load_data_dir<P: AsRef<Path>>(dir: P) -> Result<HashMap<String, Value>> {
+    let mut data = HashMap::new();
+
+    if !dir.as_ref().is_dir() {
+        return Ok(data);
+    }
+
+    for entry in WalkDir::new(dir)
+        .max_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        let (Some(stem), Some(extension)) = (
+            path.file_stem().and_then(|s| s.to_str()),
+            path.extension().and_then(|s| s.to_str()),
+        ) else {
+            continue;
+        };
+        if !path.is_file() {
+            continue;
+        }
+
+        let contents = fs::read_to_string(path)?;
+        let cannot_parse = || format!("cannot parse {}", path.to_string_lossy());
+        let value = match extension {
+            "toml" => tera::to_value(
+                toml::from_str::<toml::Value>(&contents).with_context(cannot_parse)?,
+            )?,
+            "json" => serde_json::from_str(&contents).with_context(cannot_parse)?,
+            "yaml" | "yml" => match YAML::parse(&contents) {
+                // the yaml engine doesn't report errors, it just gives up
+                Pod::Null if !contents.trim().is_empty() => {
+                    return Err(anyhow!(cannot_parse()));
+                }
+                pod => pod.deserialize().with_context(cannot_parse)?,
+            },
+            _ => continue,
+        };
+
+        data.insert(stem.to_owned(), value);
+    }
+
+    Ok(data)
+}
@@ -0,0 +1,121 @@
+//! `blog doctor`: checks that everything a build needs is where it should be,
+//! and says how to fix what isn't.
+
+use crate::{
+    config::{self, Config},
+    load_templates,
+    state::State,
+    themes, CONFIG_PATH, CONTENT_DIR, STATE_PATH, TEMPLATE_DIR, THEME_DIR,
+};
+use anyhow::{bail, Result};
+
+/// Templates every site needs, and what for.
+const REQUIRED_TEMPLATES: [(&str, &str); 2] = [
+    ("page.html", "every page without a template of its own"),
+    ("index.html", "the front page"),
+];
+
+/// Runs every check, printing what's wrong and how to fix it.
+pub fn run() -> Result<()> {
+    let mut problems = Vec::new();
+    let mut problem = |problem: String, fix: &str| problems.push((problem, fix.to_owned()));
+
+    let config_path = CONFIG_PATH.to_string_lossy();
+    if !config::find_file(&CONFIG_PATH).exists() {
+        problem(
+            format!("there's no {config_path}"),
+            "create it, with at least `title`, `base_url`, and `permalink`",
+        );
+    } else if let Err(err) = Config::from_file(&*CONFIG_PATH, None) {
+        problem(format!("{err:#}"), "fix the config, see the readme");
+    }
+
+    let content_dirs = match Config::from_file(&*CONFIG_PATH, None) {
+        Ok(config) => config
+            .content_dirs
+            .iter()
+            .map(|dir| dir.path().to_owned())
+            .collect(),
+        Err(_) => vec![CONTENT_DIR.clone()],
+    };
+    for dir in content_dirs {
+        if !dir.is_dir() {
+            problem(
+                format!("there's no {} directory", dir.to_string_lossy()),
+                "create it, and put posts in it as markdown files",
+            );
+        }
+    }
+
+    let theme = Config::from_file(&*CONFIG_PATH, None)
+        .ok()
+        .and_then(|config| config.theme);
+    let themes = match theme.as_deref().map(themes::chain) {
+        Some(Ok(themes)) => themes,
+        Some(Err(err)) => {
+            problem(
+                format!("{err:#}"),
+                "install the theme in `themes/`, or change `theme` in the config",
+            );
+            Vec::new()
+        }
+        None => Vec::new(),
+    };
+
+    let template_dir = TEMPLATE_DIR.to_string_lossy();
+    match load_templates(&themes) {
+        Ok(tera) => {
+            for (template, used_for) in REQUIRED_TEMPLATES {
+                if !tera.get_template_names().any(|name| name == template) {
+                    problem(
+                        format!("there's no {template_dir}/{template}"),
+                        &format!("create it, it's used for {used_for}"),
+                    );
+                }
+            }
+        }
+        Err(err) => {
+            let mut message = err.to_string();
+            let mut source = std::error::Error::source(&err);
+            while let Some(err) = source {
+                message.push_str(&format!(": {err}"));
+                source = err.source();
+            }
+            problem(message, "fix the template's syntax");
+        }
+    }
+
+    match syntect::highlighting::ThemeSet::load_from_folder(&*THEME_DIR) {
+        Ok(themes) if !themes.themes.is_empty() => {}
+        Ok(_) => problem(
+            format!("there are no themes in {}", THEME_DIR.to_string_lossy()),
+            "add a `.tmTheme` file for syntax highlighting",
+        ),
+        Err(err) => problem(
+            format!(
+                "cannot load themes in {}: {err}",
+                THEME_DIR.to_string_lossy()
+            ),
+            "check that the directory exists and its `.tmTheme` files are valid",
+        ),
+    }
+
+    // as a build would read it, older versions and all
+    if let Err(err) = State::load(&*STATE_PATH) {
+        problem(
+            format!("cannot read {}: {err:#}", STATE_PATH.to_string_lossy()),
+            "delete it, the next build starts fresh (but won't clean up \
+             what earlier builds wrote)",
+        );
+    }
+
+    if problems.is_empty() {
+        println!("Everything looks fine");
+        return Ok(());
+    }
+    for (problem, fix) in &problems {
+        println!("{problem}");
+        println!("  fix: {fix}");
+    }
+    bail!("{} problems", problems.len());
+}
@@ -0,0 +1,145 @@
+//! Posts as newsletter emails, rendered with `templates/email.html`.
+//!
+//! Email clients mostly ignore stylesheets and can't follow relative urls,
+//! so styles are inlined into `style` attributes and urls made absolute.
+
+use std::fs;
+
+use kuchikiki::{traits::TendrilSink, NodeRef};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::{config, links, staging::Staging, WEBSITE_DIR};
+
+lazy_static! {
+    static ref COMMENT_RE: Regex = Regex::new(r"(?s)/\*.*?\*/").unwrap();
+}
+
+/// Touches up a post rendered with the email template, at `page_url`.
+/// Stylesheets are read from what this build is writing, if it's writing
+/// them, or else from `website/`.
+pub fn to_email(html: &str, page_url: &str, staging: &Staging) -> String {
+    let document = kuchikiki::parse_html().one(html);
+
+    let mut css = String::new();
+    for link in document
+        .select("link[rel=stylesheet]")
+        .unwrap()
+        .collect::<Vec<_>>()
+    {
+        let href = link
+            .attributes
+            .borrow()
+            .get("href")
+            .unwrap_or_default()
+            .to_owned();
+        // only the site's own stylesheets can be inlined
+        let page_dir = links::link_target(page_url, &WEBSITE_DIR);
+        let Some(path) = links::link_target(&href, page_dir.as_deref().unwrap_or(&WEBSITE_DIR))
+        else {
+            continue;
+        };
+        let stylesheet = match staging.contents(&path) {
+            Some(contents) => Some(String::from_utf8_lossy(&contents).into_owned()),
+            None => fs::read_to_string(&path).ok(),
+        };
+        if let Some(stylesheet) = stylesheet {
+            css.push_str(&stylesheet);
+            link.as_node().detach();
+        }
+    }
+    for style in document.select("style").unwrap().collect::<Vec<_>>() {
+        css.push_str(&style.as_node().text_contents());
+        style.as_node().detach();
+    }
+    inline_css(&document, &css);
+
+    for (selector, attribute) in [("img[src]", "src"), ("a[href]", "href")] {
+        for element in document.select(selector).unwrap() {
+            let mut attributes = element.attributes.borrow_mut();
+            let url = attributes.get(attribute).unwrap_or_default();
+            if let Some(url) = absolute_url(url, page_url) {
+                attributes.insert(attribute, url);
+            }
+        }
+    }
+
+    document.to_string()
+}
+
+/// Adds the declarations of each rule in `css` to the `style` of the
+/// elements it matches, before any they already have. At-rules, like
+/// `@media`, are left out.
+fn inline_css(document: &NodeRef, css: &str) {
+    let css = COMMENT_RE.replace_all(css, "");
+    let mut rest = css.as_ref();
+    let mut rules = Vec::new();
+    while let Some(open) = rest.find('{') {
+        let selectors = rest[..open].trim();
+        // find the matching brace, for at-rules with rules inside
+        let mut depth = 0;
+        let mut close = rest.len();
+        for (i, c) in rest[open..].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => continue,
+            }
+            if depth == 0 {
+                close = open + i;
+                break;
+            }
+        }
+        let declarations = rest[open + 1..close].trim();
+        if !selectors.starts_with('@') && !declarations.is_empty() {
+            rules.push((
+                selectors.to_owned(),
+                declarations
+                    .trim_end_matches(';')
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ));
+        }
+        rest = rest.get(close + 1..).unwrap_or_default();
+    }
+
+    let mut styles: Vec<(NodeRef, String)> = Vec::new();
+    for (selectors, declarations) in &rules {
+        for selector in selectors.split(',') {
+            // pseudo-classes like `:hover` can't be inlined
+            let Ok(elements) = document.select(selector.trim()) else {
+                continue;
+            };
+            for element in elements {
+                let node = element.as_node().clone();
+                match styles.iter_mut().find(|(n, _)| *n == node) {
+                    Some((_, style)) => style.push_str(&format!("; {declarations}")),
+                    None => styles.push((node, declarations.clone())),
+                }
+            }
+        }
+    }
+
+    for (node, style) in styles {
+        let element = node.as_element().unwrap();
+        let mut attributes = element.attributes.borrow_mut();
+        let style = match attributes.get("style") {
+            Some(existing) => format!("{style}; {existing}"),
+            None => style,
+        };
+        attributes.insert("style", style);
+    }
+}
+
+/// `url`, from the page at `page_url`, as an absolute url. Urls that are
+/// already absolute, or just fragments, are left alone.
+fn absolute_url(url: &str, page_url: &str) -> Option<String> {
+    if url.contains(':') || url.starts_with("//") || url.starts_with('#') {
+        return None;
+    }
+    match url.strip_prefix('/') {
+        Some(_) => Some(format!("{}{url}", config().origin())),
+        None => Some(config().absolute_url(&format!("{page_url}{url}"))),
+    }
+}
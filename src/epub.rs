@@ -0,0 +1,370 @@
+//! `blog export epub`: posts bundled into an EPUB, with a table of contents.
+//!
+//! Chapters are the posts' rendered bodies, rewritten as XHTML, with their
+//! images copied in.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::{NaiveDate, Utc};
+use kuchikiki::{traits::TendrilSink, NodeData, NodeRef};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    config,
+    html::{escape, get_body_children_of_document},
+    staging::Staging,
+};
+
+/// Elements without a closing tag.
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+struct Chapter {
+    title: String,
+    date: Option<NaiveDate>,
+    /// The chapter's XHTML body.
+    body: String,
+}
+
+/// A book of posts, put together as they're rendered.
+pub struct Book {
+    title: String,
+    chapters: Vec<Chapter>,
+    /// Images, as where they're copied to for the site and where they go in
+    /// the book.
+    images: Vec<(PathBuf, String)>,
+}
+
+impl Book {
+    pub fn new(title: String) -> Book {
+        Book {
+            title,
+            chapters: Vec::new(),
+            images: Vec::new(),
+        }
+    }
+
+    /// Adds a post, given its rendered body and the directory its images
+    /// are copied to. Chapters are in date order, whatever order they're
+    /// added in.
+    pub fn add_chapter(
+        &mut self,
+        page_dir: &Path,
+        slug: &str,
+        title: &str,
+        date: Option<NaiveDate>,
+        body: &str,
+    ) {
+        let document = kuchikiki::parse_html().one(body);
+
+        // each post's images get their own directory, since image paths are
+        // only unique within a post
+        let dir = format!("images/{slug}");
+        for img in document.select("img").unwrap() {
+            let mut attributes = img.attributes.borrow_mut();
+            let src = attributes.get("src").unwrap_or_default().to_owned();
+            if src.is_empty() || src.contains("://") || src.starts_with("//") {
+                continue;
+            }
+            let book_path = format!("{dir}/{}", src.trim_start_matches('/'));
+            if !self.images.iter().any(|(_, path)| *path == book_path) {
+                self.images.push((page_dir.join(&src), book_path.clone()));
+            }
+            attributes.insert("src", book_path);
+        }
+
+        let mut xhtml = format!("<h1>{}</h1>\n", escape(title));
+        if let Some(date) = date {
+            xhtml.push_str(&format!("<p>{date}</p>\n"));
+        }
+        for node in get_body_children_of_document(&document) {
+            write_xhtml(&node, &mut xhtml);
+        }
+        self.chapters.push(Chapter {
+            title: title.to_owned(),
+            date,
+            body: xhtml,
+        });
+        self.chapters.sort_by_key(|chapter| chapter.date);
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chapters.is_empty()
+    }
+
+    /// Writes the book to `path`. Images are read as `staging` has them, so
+    /// ones made during the build, like thumbnails, are there too.
+    pub fn write(&self, path: &Path, staging: &Staging) -> Result<()> {
+        let mut zip = ZipWriter::new(io::Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        let mut add = |name: &str, contents: &[u8]| -> Result<()> {
+            zip.start_file(name, options)?;
+            zip.write_all(contents)?;
+            Ok(())
+        };
+        // has to come first, and uncompressed
+        add("mimetype", b"application/epub+zip")?;
+        add(
+            "META-INF/container.xml",
+            br#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#,
+        )?;
+
+        let mut manifest = String::new();
+        let mut spine = String::new();
+        let mut toc = String::new();
+        for (i, chapter) in self.chapters.iter().enumerate() {
+            let name = format!("chapter-{}.xhtml", i + 1);
+            manifest.push_str(&format!(
+                "    <item id=\"chapter-{}\" href=\"{name}\" media-type=\"application/xhtml+xml\"/>\n",
+                i + 1
+            ));
+            spine.push_str(&format!("    <itemref idref=\"chapter-{}\"/>\n", i + 1));
+            toc.push_str(&format!(
+                "      <li><a href=\"{name}\">{}</a></li>\n",
+                escape(&chapter.title)
+            ));
+            add(
+                &format!("OEBPS/{name}"),
+                xhtml_document(&chapter.title, &chapter.body).as_bytes(),
+            )?;
+        }
+        for (i, (from, to)) in self.images.iter().enumerate() {
+            let contents = match staging.contents(from) {
+                Some(contents) => contents.into_owned(),
+                None => fs::read(from)
+                    .with_context(|| format!("cannot read {}", from.to_string_lossy()))?,
+            };
+            manifest.push_str(&format!(
+                "    <item id=\"image-{}\" href=\"{}\" media-type=\"{}\"/>\n",
+                i + 1,
+                escape(to),
+                media_type(to)
+            ));
+            add(&format!("OEBPS/{to}"), &contents)?;
+        }
+
+        let nav = format!("    <nav epub:type=\"toc\">\n      <h1>Contents</h1>\n      <ol>\n{toc}      </ol>\n    </nav>\n");
+        add(
+            "OEBPS/nav.xhtml",
+            xhtml_document("Contents", &nav).as_bytes(),
+        )?;
+
+        let config = config();
+        let opf = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="id">{identifier}</dc:identifier>
+    <dc:title>{title}</dc:title>
+    <dc:creator>{author}</dc:creator>
+    <dc:language>{language}</dc:language>
+    <meta property="dcterms:modified">{modified}</meta>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+{manifest}  </manifest>
+  <spine>
+{spine}  </spine>
+</package>
+"#,
+            identifier = escape(&config.absolute_url(&format!("#{}", slug::slugify(&self.title)))),
+            title = escape(&self.title),
+            author = escape(&config.author),
+            language = escape(&config.language),
+            modified = Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+        );
+        add("OEBPS/content.opf", opf.as_bytes())?;
+
+        let contents = zip.finish()?.into_inner();
+        fs::write(path, contents)
+            .with_context(|| format!("cannot write {}", path.to_string_lossy()))?;
+        Ok(())
+    }
+}
+
+fn xhtml_document(title: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+  <meta charset="utf-8"/>
+  <title>{}</title>
+</head>
+<body>
+{body}</body>
+</html>
+"#,
+        escape(title)
+    )
+}
+
+/// Writes `node` as XHTML, which unlike html needs void elements closed and
+/// everything escaped.
+fn write_xhtml(node: &NodeRef, output: &mut String) {
+    match node.data() {
+        NodeData::Text(text) => output.push_str(&escape(&text.borrow())),
+        NodeData::Element(element) => {
+            let name = element.name.local.as_ref();
+            output.push('<');
+            output.push_str(name);
+            // html knows these without being told
+            match name {
+                "svg" => output.push_str(r#" xmlns="http://www.w3.org/2000/svg""#),
+                "math" => output.push_str(r#" xmlns="http://www.w3.org/1998/Math/MathML""#),
+                _ => {}
+            }
+            for (attribute, value) in element.attributes.borrow().map.iter() {
+                output.push_str(&format!(
+                    " {}=\"{}\"",
+                    attribute.local.as_ref(),
+                    escape(&value.value)
+                ));
+            }
+            if VOID_ELEMENTS.contains(&name) {
+                output.push_str("/>");
+                return;
+            }
+            output.push('>');
+            for child in node.children() {
+                write_xhtml(&child, output);
+            }
+            output.push_str(&format!("</{name}>"));
+        }
+        // comments, like the ones html shortcodes leave, aren't needed
+        _ => {
+            for child in node.children() {
+                write_xhtml(&child, output);
+            }
+        }
+    }
+}
+
+fn media_type(path: &str) -> &'static str {
+    let extension = path.rsplit('.').next().unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn reads_back_as_a_zip() {
+        crate::load_default_config();
+        let dir = std::env::temp_dir().join(format!("blog-epub-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("cat.png"), b"not really a png").unwrap();
+
+        let mut book = Book::new("Posts & things".to_owned());
+        book.add_chapter(
+            &dir,
+            "second",
+            "Second",
+            NaiveDate::from_ymd_opt(2024, 5, 2),
+            "<p>two<br>lines</p>",
+        );
+        book.add_chapter(
+            &dir,
+            "first",
+            "First <post>",
+            NaiveDate::from_ymd_opt(2024, 5, 1),
+            r#"<p><img src="cat.png" alt="a cat"></p>"#,
+        );
+        let path = dir.join("book.epub");
+        book.write(&path, &Staging::default()).unwrap();
+
+        let contents = fs::read(&path).unwrap();
+        // the mimetype is first, stored, so it can be read at a fixed offset
+        assert_eq!(&contents[30..38], b"mimetype");
+        assert_eq!(&contents[38..58], b"application/epub+zip");
+
+        let mut zip = zip::ZipArchive::new(io::Cursor::new(contents)).unwrap();
+        let mut read = |name: &str| {
+            let mut text = String::new();
+            zip.by_name(name)
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        };
+        let first = read("OEBPS/chapter-1.xhtml");
+        assert!(first.contains("<h1>First &lt;post&gt;</h1>"));
+        assert!(first.contains(r#"<img src="images/first/cat.png" alt="a cat"/>"#));
+        assert!(read("OEBPS/chapter-2.xhtml").contains("two<br/>lines"));
+        assert_eq!(read("OEBPS/images/first/cat.png"), "not really a png");
+        assert!(read("OEBPS/content.opf").contains("<dc:title>Posts &amp; things</dc:title>"));
+        assert!(read("META-INF/container.xml").contains("OEBPS/content.opf"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn exports_gallery_thumbnails() {
+        crate::load_default_config();
+        let dir = std::env::temp_dir().join(format!("blog-epub-gallery-{}", std::process::id()));
+        let content_dir = dir.join("content");
+        let page_dir = dir.join("website/trip");
+        fs::create_dir_all(&content_dir).unwrap();
+        image::RgbImage::new(1200, 600)
+            .save(content_dir.join("big.jpg"))
+            .unwrap();
+
+        // as the gallery shortcode renders it, then as a build processes it
+        let document = kuchikiki::parse_html().one(
+            r#"<div class="gallery"><a href="big.jpg"><img src="big.jpg" alt="" data-thumbnail></a></div>"#,
+        );
+        let mut staging = Staging::default();
+        for (from, to) in
+            crate::html::find_media_and_add_dimensions(&document, &content_dir, &page_dir)
+        {
+            staging.copy(from, to);
+        }
+        let body: String = get_body_children_of_document(&document)
+            .map(|node| node.to_string())
+            .collect();
+
+        let mut book = Book::new("Trips".to_owned());
+        book.add_chapter(&page_dir, "trip", "Trip", None, &body);
+        let path = dir.join("book.epub");
+        book.write(&path, &staging).unwrap();
+
+        let mut zip = zip::ZipArchive::new(fs::File::open(&path).unwrap()).unwrap();
+        let mut chapter = String::new();
+        zip.by_name("OEBPS/chapter-1.xhtml")
+            .unwrap()
+            .read_to_string(&mut chapter)
+            .unwrap();
+        assert!(chapter.contains(r#"src="images/trip/big.thumb.jpg""#));
+        let thumbnail = zip.by_name("OEBPS/images/trip/big.thumb.jpg").unwrap();
+        assert!(thumbnail.size() > 0);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+//! The usual set of favicons, made from one png or svg: an `.ico`, a 32px
+//! png, a 180px apple touch icon, and a 512px maskable icon for web app
+//! manifests. An svg is also used as it is, for browsers that take one.
+
+use std::{fs, io::Cursor, path::Path};
+
+use anyhow::{bail, Context, Result};
+use image::{
+    codecs::ico::{IcoEncoder, IcoFrame},
+    imageops::{self, FilterType},
+    DynamicImage, ExtendedColorType, ImageFormat, Rgba, RgbaImage,
+};
+use resvg::{tiny_skia, usvg};
+use serde::Serialize;
+
+use crate::{config::FaviconConfig, staging::Staging, WEBSITE_DIR};
+
+/// Where the favicons are, as templates see them in `favicons`.
+#[derive(Serialize, Default)]
+pub struct Favicons {
+    pub svg: Option<String>,
+    pub ico: Option<String>,
+    pub png: Option<String>,
+    pub apple_touch: Option<String>,
+    pub maskable: Option<String>,
+}
+
+/// Sizes packed into `favicon.ico`.
+const ICO_SIZES: [u32; 3] = [16, 32, 48];
+/// How much of a maskable icon is safe from being cropped.
+const MASKABLE_SAFE_ZONE: f32 = 0.8;
+
+/// What the favicons are drawn from.
+enum Source {
+    Raster(DynamicImage),
+    /// Drawn at each size, rather than scaled.
+    Svg(Box<usvg::Tree>),
+}
+
+impl Source {
+    fn read(path: &Path) -> Result<Source> {
+        let contents = fs::read(path)?;
+        match path.extension().is_some_and(|e| e == "svg") {
+            true => {
+                let tree = usvg::Tree::from_data(&contents, &usvg::Options::default())?;
+                Ok(Source::Svg(Box::new(tree)))
+            }
+            false => Ok(Source::Raster(image::load_from_memory(&contents)?)),
+        }
+    }
+
+    /// The image, scaled to fit in the middle of a transparent square of
+    /// `size`.
+    fn fit(&self, size: u32) -> RgbaImage {
+        match self {
+            Source::Raster(image) => {
+                let scaled = image.resize(size, size, FilterType::Lanczos3).to_rgba8();
+                let mut square = RgbaImage::new(size, size);
+                let x = (size - scaled.width()) / 2;
+                let y = (size - scaled.height()) / 2;
+                imageops::overlay(&mut square, &scaled, x.into(), y.into());
+                square
+            }
+            Source::Svg(tree) => {
+                let (width, height) = (tree.size().width(), tree.size().height());
+                let scale = size as f32 / width.max(height);
+                let transform = tiny_skia::Transform::from_scale(scale, scale).post_translate(
+                    (size as f32 - width * scale) / 2.0,
+                    (size as f32 - height * scale) / 2.0,
+                );
+                let mut pixmap = tiny_skia::Pixmap::new(size, size).expect("sizes aren't 0");
+                resvg::render(tree, transform, &mut pixmap.as_mut());
+                RgbaImage::from_fn(size, size, |x, y| {
+                    let pixel = pixmap.pixels()[(y * size + x) as usize].demultiply();
+                    Rgba([pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()])
+                })
+            }
+        }
+    }
+
+    /// The image fit in a `size` square, a `fill` of it, on `background`.
+    fn on_background(&self, size: u32, fill: f32, background: Rgba<u8>) -> RgbaImage {
+        let inner = (size as f32 * fill) as u32;
+        let mut image = RgbaImage::from_pixel(size, size, background);
+        let offset = ((size - inner) / 2).into();
+        imageops::overlay(&mut image, &self.fit(inner), offset, offset);
+        image
+    }
+}
+
+/// Makes the favicons from `options.source`, staging them to be written.
+pub fn generate(options: &FaviconConfig, staging: &mut Staging) -> Result<Favicons> {
+    let path = Path::new(&options.source);
+    let mut favicons = Favicons::default();
+
+    if path.extension().is_some_and(|e| e == "svg") {
+        let to = WEBSITE_DIR.join("favicon.svg");
+        if path != to {
+            staging.copy(path, &to);
+        }
+        favicons.svg = Some("/favicon.svg".to_owned());
+    }
+
+    let source =
+        Source::read(path).with_context(|| format!("cannot read {}", path.to_string_lossy()))?;
+
+    let mut frames = Vec::new();
+    for size in ICO_SIZES {
+        let image = source.fit(size);
+        frames.push(IcoFrame::as_png(
+            image.as_raw(),
+            size,
+            size,
+            ExtendedColorType::Rgba8,
+        )?);
+    }
+    let mut ico = Vec::new();
+    IcoEncoder::new(&mut ico).encode_images(&frames)?;
+    staging.write_bytes(WEBSITE_DIR.join("favicon.ico"), ico);
+    favicons.ico = Some("/favicon.ico".to_owned());
+
+    staging.write_bytes(
+        WEBSITE_DIR.join("favicon-32x32.png"),
+        encode_png(&source.fit(32))?,
+    );
+    favicons.png = Some("/favicon-32x32.png".to_owned());
+
+    // apple touch icons get black corners where they're transparent
+    let background = parse_color(&options.background)?;
+    staging.write_bytes(
+        WEBSITE_DIR.join("apple-touch-icon.png"),
+        encode_png(&source.on_background(180, 1.0, background))?,
+    );
+    favicons.apple_touch = Some("/apple-touch-icon.png".to_owned());
+
+    staging.write_bytes(
+        WEBSITE_DIR.join("icon-maskable.png"),
+        encode_png(&source.on_background(512, MASKABLE_SAFE_ZONE, background))?,
+    );
+    favicons.maskable = Some("/icon-maskable.png".to_owned());
+
+    Ok(favicons)
+}
+
+fn encode_png(image: &RgbaImage) -> Result<Vec<u8>> {
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+/// Reads colors like `#fff` or `#ffffff`.
+fn parse_color(color: &str) -> Result<Rgba<u8>> {
+    let hex = color.trim_start_matches('#');
+    let hex = match hex.len() {
+        3 => hex.chars().flat_map(|c| [c, c]).collect(),
+        _ => hex.to_owned(),
+    };
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2).unwrap_or_default(), 16);
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Ok(r), Ok(g), Ok(b)) => Ok(Rgba([r, g, b, 255])),
+        _ => bail!("cannot read color `{color}`"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GenericImageView, ImageBuffer, LumaA};
+
+    use super::*;
+
+    /// Makes the favicons from `source`, written to a file called `name`,
+    /// and returns what's staged for each of them.
+    fn generate_from(name: &str, source: &[u8]) -> Vec<DynamicImage> {
+        let path = std::env::temp_dir().join(format!("blog-favicon-test-{name}"));
+        fs::write(&path, source).unwrap();
+        let options = FaviconConfig {
+            source: path.to_string_lossy().into_owned(),
+            background: "#f00".to_owned(),
+        };
+        let mut staging = Staging::default();
+        generate(&options, &mut staging).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        [
+            "favicon.ico",
+            "favicon-32x32.png",
+            "apple-touch-icon.png",
+            "icon-maskable.png",
+        ]
+        .map(|name| {
+            let contents = staging.contents(&WEBSITE_DIR.join(name)).unwrap();
+            image::load_from_memory(&contents).unwrap()
+        })
+        .into()
+    }
+
+    fn sizes(images: &[DynamicImage]) -> Vec<(u32, u32)> {
+        images.iter().map(|image| image.dimensions()).collect()
+    }
+
+    #[test]
+    fn makes_the_set_from_an_svg() {
+        let svg = br##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 10 20"><rect width="10" height="20" fill="#00f"/></svg>"##;
+        let images = generate_from("tall.svg", svg);
+        // the ico is read as its largest size
+        assert_eq!(sizes(&images), [(48, 48), (32, 32), (180, 180), (512, 512)]);
+
+        let png = &images[1];
+        assert_eq!(png.get_pixel(16, 16), Rgba([0, 0, 255, 255]));
+        // a tall source is centered, with transparent sides
+        assert_eq!(png.get_pixel(0, 16)[3], 0);
+        // and the apple touch icon has the background behind it
+        assert_eq!(images[2].get_pixel(0, 90), Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn makes_the_set_from_a_grey_16_bit_png() {
+        let grey: ImageBuffer<LumaA<u16>, Vec<u16>> =
+            ImageBuffer::from_pixel(64, 64, LumaA([0x8080, 0xffff]));
+        let mut png = Vec::new();
+        DynamicImage::from(grey)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let images = generate_from("grey.png", &png);
+        assert_eq!(sizes(&images), [(48, 48), (32, 32), (180, 180), (512, 512)]);
+        assert_eq!(images[1].get_pixel(16, 16), Rgba([128, 128, 128, 255]));
+    }
+
+    #[test]
+    fn reads_colors() {
+        assert_eq!(parse_color("#fff").unwrap(), Rgba([255, 255, 255, 255]));
+        assert_eq!(
+            parse_color("123456").unwrap(),
+            Rgba([0x12, 0x34, 0x56, 255])
+        );
+        assert!(parse_color("#ff").is_err());
+    }
+}
@@ -0,0 +1,80 @@
+//! Atom feeds of posts, one per tag, at `/tags/<tag>/feed.xml`.
+
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::{config, html::escape, PageContext};
+
+/// A feed, as templates see it in `feeds`, e.g. for autodiscovery links.
+#[derive(Serialize, Clone)]
+pub struct Feed {
+    pub tag: String,
+    pub title: String,
+    pub url: String,
+}
+
+impl Feed {
+    pub fn new(tag: &str) -> Feed {
+        Feed {
+            tag: tag.to_owned(),
+            title: format!("{}: {tag}", config().title),
+            url: format!("/tags/{}/feed.xml", slug::slugify(tag)),
+        }
+    }
+}
+
+/// `feed` as an Atom feed of `posts`, which should be newest first.
+pub fn atom(feed: &Feed, posts: &[&PageContext]) -> String {
+    let config = config();
+    let timestamp = |post: &PageContext| {
+        post.updated.clone().or_else(|| {
+            post.iso_date
+                .as_ref()
+                .map(|date| format!("{date}T00:00:00Z"))
+        })
+    };
+    let updated = posts
+        .iter()
+        .filter_map(|post| timestamp(post))
+        .max()
+        .unwrap_or_else(|| Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string());
+
+    let mut entries = String::new();
+    for post in posts {
+        let url = config.absolute_url(&post.url);
+        entries.push_str(&format!(
+            r#"  <entry>
+    <title>{title}</title>
+    <link href="{url}"/>
+    <id>{url}</id>
+    <updated>{updated}</updated>
+    <content type="html" xml:base="{url}">{content}</content>
+  </entry>
+"#,
+            title = escape(&post.title),
+            url = escape(&url),
+            updated = timestamp(post).unwrap_or_else(|| updated.clone()),
+            content = escape(&post.contents),
+        ));
+    }
+
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xml:lang="{language}">
+  <title>{title}</title>
+  <link href="{site}"/>
+  <link rel="self" href="{url}"/>
+  <id>{url}</id>
+  <updated>{updated}</updated>
+  <author>
+    <name>{author}</name>
+  </author>
+{entries}</feed>
+"#,
+        language = escape(&config.language),
+        title = escape(&feed.title),
+        site = escape(&config.absolute_url("/")),
+        url = escape(&config.absolute_url(&feed.url)),
+        author = escape(&config.author),
+    )
+}
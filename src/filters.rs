@@ -0,0 +1,298 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use chrono::{DateTime, Locale};
+use log::{debug, warn};
+use rhai::{
+    module_resolvers::DummyModuleResolver,
+    serde::{from_dynamic, to_dynamic},
+    Dynamic, Engine, Scope, AST,
+};
+use tera::{try_get_value, Context, ErrorKind, Filter, Function, Tera, Value};
+use walkdir::WalkDir;
+
+use crate::{config, markdown_to_html, parse_date, TEMPLATE_DIR};
+
+/// Cuts a string down to its first `length` words (default 50), appending
+/// `end` (default "…") if anything was cut.
+pub fn truncate_words(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let s = try_get_value!("truncate_words", "value", String, value);
+    let length = match args.get("length") {
+        Some(length) => try_get_value!("truncate_words", "length", usize, length),
+        None => 50,
+    };
+    let end = match args.get("end") {
+        Some(end) => try_get_value!("truncate_words", "end", String, end),
+        None => "…".to_owned(),
+    };
+
+    let words: Vec<&str> = s.split_whitespace().collect();
+    if words.len() <= length {
+        return Ok(Value::String(s));
+    }
+    Ok(Value::String(words[..length].join(" ") + end.as_str()))
+}
+
+/// Renders a string as markdown, using the same options as page contents.
+/// With `inline=true`, the wrapping `<p>` tags are dropped.
+pub fn markdown(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let s = try_get_value!("markdown", "value", String, value);
+    let inline = match args.get("inline") {
+        Some(inline) => try_get_value!("markdown", "inline", bool, inline),
+        None => false,
+    };
+
+    let html = markdown_to_html(&s, &config().markdown);
+    if inline {
+        let trimmed = html.trim_end();
+        if let Some(inner) = trimmed
+            .strip_prefix("<p>")
+            .and_then(|s| s.strip_suffix("</p>"))
+        {
+            // only unwrap if it's a single paragraph
+            if !inner.contains("<p>") {
+                return Ok(Value::String(inner.to_owned()));
+            }
+        }
+    }
+    Ok(Value::String(html))
+}
+
+/// Makes a site path relative to the root, under `base_url`'s path, e.g.
+/// `page.url | url_for`, or a full url with `absolute=true`, for canonical
+/// links and the like.
+pub fn url_for(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let path = try_get_value!("url_for", "value", String, value);
+    let absolute = match args.get("absolute") {
+        Some(absolute) => try_get_value!("url_for", "absolute", bool, absolute),
+        None => false,
+    };
+    Ok(Value::String(match absolute {
+        true => config().absolute_url(&path),
+        false => config().root_relative_url(&path),
+    }))
+}
+
+/// Formats a date like `2024-03-03`, an RFC 3339 datetime, or a unix
+/// timestamp, e.g. `date | date(format="%-d %B %Y", locale="fr_FR")`.
+/// `format` and `locale` default to the config's `date_format` and `locale`.
+pub fn date(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let format = match args.get("format") {
+        Some(format) => try_get_value!("date", "format", String, format),
+        None => config().date_format.clone(),
+    };
+    let locale = match args.get("locale") {
+        Some(locale) => try_get_value!("date", "locale", String, locale),
+        None => config().locale.clone(),
+    };
+    let locale = Locale::try_from(locale.as_str())
+        .map_err(|_| format!("`date`: unknown locale `{locale}`"))?;
+
+    let mut formatted = String::new();
+    let result = match value {
+        Value::Number(n) => {
+            let Some(datetime) = n.as_i64().and_then(|n| DateTime::from_timestamp(n, 0)) else {
+                return Err(format!("`date`: {n} isn't a timestamp").into());
+            };
+            write!(formatted, "{}", datetime.format_localized(&format, locale))
+        }
+        Value::String(s) => {
+            if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+                write!(formatted, "{}", datetime.format_localized(&format, locale))
+            } else if let Some(date) = parse_date(s) {
+                write!(formatted, "{}", date.format_localized(&format, locale))
+            } else {
+                return Err(format!("`date`: cannot read `{s}` as a date").into());
+            }
+        }
+        _ => return Err(format!("`date`: cannot read `{value}` as a date").into()),
+    };
+    // chrono reports bad format strings when the date is written out
+    result.map_err(|_| format!("`date`: invalid format `{format}`"))?;
+
+    Ok(Value::String(formatted))
+}
+
+/// A rhai script in `templates/_filters/`, usable as a filter and a function
+/// named after it, e.g. `_filters/shout.rhai` is `value | shout` and
+/// `shout(...)`. It sees the filtered `value` (`()` when called as a
+/// function) and the `args` map, and its last expression is the result.
+#[derive(Clone)]
+pub struct Script {
+    path: PathBuf,
+    engine: Arc<Engine>,
+    ast: AST,
+}
+
+impl Script {
+    fn run(&self, value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let error = |err: &dyn std::fmt::Display| {
+            tera::Error::msg(format!("{}: {err}", self.path.to_string_lossy()))
+        };
+        let mut scope = Scope::new();
+        scope.push_dynamic("value", to_dynamic(value).map_err(|e| error(&e))?);
+        scope.push_dynamic("args", to_dynamic(args).map_err(|e| error(&e))?);
+        let result: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| error(&e))?;
+        from_dynamic(&result).map_err(|e| error(&e))
+    }
+}
+
+impl Filter for Script {
+    fn filter(&self, value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        self.run(value, args)
+    }
+}
+
+impl Function for Script {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        self.run(&Value::Null, args)
+    }
+}
+
+/// The engine scripts run in: it can't touch anything outside the script,
+/// and a script that runs away is stopped.
+fn script_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_module_resolver(DummyModuleResolver::new());
+    engine.set_max_operations(1_000_000);
+    engine.set_max_call_levels(64);
+    engine.on_print(|text| debug!("{text}"));
+    engine.on_debug(|text, _, _| debug!("{text}"));
+    engine
+}
+
+/// The filters and functions the blog adds to tera's own.
+const OWN: [&str; 5] = ["date", "truncate_words", "markdown", "url_for", "get_page"];
+
+/// Whether `name` is one of tera's filters or functions, or one of [`OWN`].
+fn is_taken(name: &str) -> bool {
+    if OWN.contains(&name) {
+        return true;
+    }
+    let mut tera = Tera::default();
+    let mut taken = |template: String| match tera.render_str(&template, &Context::new()) {
+        Ok(_) => true,
+        Err(err) => {
+            let mut source: Option<&dyn std::error::Error> = Some(&err);
+            while let Some(err) = source {
+                if let Some(err) = err.downcast_ref::<tera::Error>() {
+                    if matches!(
+                        err.kind,
+                        ErrorKind::FilterNotFound(_) | ErrorKind::FunctionNotFound(_)
+                    ) {
+                        return false;
+                    }
+                }
+                source = err.source();
+            }
+            // it's there, but didn't like being called without arguments
+            true
+        }
+    };
+    taken(format!("{{{{ 0 | {name} }}}}")) || taken(format!("{{{{ {name}() }}}}"))
+}
+
+/// Registers the scripts in the site's `templates/_filters/`, then those in
+/// each of `themes`' that the site doesn't have, by name. A script can't
+/// take the place of one of tera's or the blog's own filters and functions.
+pub fn register_scripts(tera: &mut Tera, themes: &[PathBuf]) -> anyhow::Result<()> {
+    let engine = Arc::new(script_engine());
+    let mut scripts = BTreeMap::new();
+    let dirs =
+        std::iter::once(TEMPLATE_DIR.clone()).chain(themes.iter().map(|t| t.join("templates")));
+    for dir in dirs.map(|dir| dir.join("_filters")) {
+        let entries = WalkDir::new(&dir).max_depth(1).into_iter();
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.into_path()) {
+            if !path.is_file() || path.extension().is_none_or(|e| e != "rhai") {
+                continue;
+            }
+            let name = path.file_stem().unwrap_or_default().to_string_lossy();
+            scripts.entry(name.into_owned()).or_insert(path);
+        }
+    }
+
+    for (name, path) in scripts {
+        let is_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_name {
+            warn!(
+                "{}: `{name}` can't be a filter's name",
+                path.to_string_lossy()
+            );
+            continue;
+        }
+        if is_taken(&name) {
+            warn!(
+                "{}: there's already a `{name}`, so it isn't used",
+                path.to_string_lossy()
+            );
+            continue;
+        }
+        let ast = engine
+            .compile_file(path.clone())
+            .map_err(|err| anyhow::anyhow!("{}: {err}", path.to_string_lossy()))?;
+        let script = Script {
+            path,
+            engine: Arc::clone(&engine),
+            ast,
+        };
+        tera.register_filter(&name, script.clone());
+        tera.register_function(&name, script);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(source: &str) -> Script {
+        let engine = Arc::new(script_engine());
+        let ast = engine.compile(source).unwrap();
+        Script {
+            path: "shout.rhai".into(),
+            engine,
+            ast,
+        }
+    }
+
+    #[test]
+    fn scripts_get_the_value_and_args() {
+        let shout = script(r#"value.to_upper() + args.end"#);
+        let args = HashMap::from([("end".to_owned(), Value::from("!"))]);
+        let result = shout.filter(&Value::from("hi"), &args).unwrap();
+        assert_eq!(result, Value::from("HI!"));
+    }
+
+    #[test]
+    fn scripts_return_json() {
+        let list = script(r#"#{ words: args.text.split(" "), function: value == () }"#);
+        let args = HashMap::from([("text".to_owned(), Value::from("a b"))]);
+        let result = list.call(&args).unwrap();
+        assert_eq!(
+            result,
+            serde_json::json!({ "words": ["a", "b"], "function": true })
+        );
+    }
+
+    #[test]
+    fn runaway_scripts_are_stopped() {
+        let spin = script("loop {}");
+        assert!(spin.filter(&Value::Null, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn builtins_are_taken() {
+        assert!(is_taken("upper"));
+        assert!(is_taken("range"));
+        assert!(is_taken("get_page"));
+        assert!(!is_taken("shout"));
+    }
+}
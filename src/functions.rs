@@ -0,0 +1,28 @@
+use std::collections::HashMap;
+
+use tera::{try_get_value, Value};
+
+use crate::{filters, site};
+
+/// Looks up another page's title, date, slug, and url by its slug, e.g.
+/// `{% set about = get_page(slug="about-this-blog") %}`.
+pub fn get_page(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let Some(slug) = args.get("slug") else {
+        return Err("`get_page` requires a `slug` argument".into());
+    };
+    let slug = try_get_value!("get_page", "slug", String, slug);
+
+    match site().pages.get().and_then(|pages| pages.get(&slug)) {
+        Some(page) => Ok(tera::to_value(page)?),
+        None => Err(format!("`get_page`: no page with slug `{slug}`").into()),
+    }
+}
+
+/// Makes a site path relative to the root, e.g. `url_for(path="style.css")`,
+/// as the `url_for` filter does.
+pub fn url_for(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let Some(path) = args.get("path") else {
+        return Err("`url_for` requires a `path` argument".into());
+    };
+    filters::url_for(path, args)
+}
@@ -0,0 +1,234 @@
+//! Gemtext, for a Gemini capsule built alongside the website.
+//!
+//! Gemtext has headings, lists, quotes, and preformatted blocks, but no
+//! inline formatting or links, so formatting is dropped, and links and
+//! images become link lines after the paragraph they're in.
+
+use std::collections::HashMap;
+
+use markdown::mdast::Node;
+
+use crate::{config::MarkdownConfig, parse_options};
+
+/// A page's markdown as gemtext.
+pub struct Gemtext {
+    pub text: String,
+    /// Local images it links to, relative to `content/`.
+    pub images: Vec<String>,
+}
+
+/// Turns `markdown` into gemtext. `@/` links are pointed at the pages
+/// they're to, using `urls` (by path under `content/`).
+pub fn to_gemtext(
+    markdown: &str,
+    options: &MarkdownConfig,
+    urls: &HashMap<String, String>,
+) -> Gemtext {
+    let root = markdown::to_mdast(markdown, &parse_options(options)).unwrap();
+
+    let mut writer = Writer {
+        definitions: HashMap::new(),
+        urls,
+        hard_line_breaks: options.hard_line_breaks,
+        images: Vec::new(),
+    };
+    writer.find_definitions(&root);
+    let mut blocks = Vec::new();
+    writer.block(&root, &mut blocks);
+
+    Gemtext {
+        text: blocks.join("\n\n"),
+        images: writer.images,
+    }
+}
+
+struct Writer<'a> {
+    /// Link reference definitions, by identifier.
+    definitions: HashMap<String, String>,
+    urls: &'a HashMap<String, String>,
+    hard_line_breaks: bool,
+    images: Vec<String>,
+}
+
+impl Writer<'_> {
+    fn find_definitions(&mut self, node: &Node) {
+        if let Node::Definition(definition) = node {
+            self.definitions
+                .insert(definition.identifier.clone(), definition.url.clone());
+        }
+        for child in node.children().into_iter().flatten() {
+            self.find_definitions(child);
+        }
+    }
+
+    /// Adds `node`, a block, to `blocks`.
+    fn block(&mut self, node: &Node, blocks: &mut Vec<String>) {
+        match node {
+            Node::Root(root) => {
+                for child in &root.children {
+                    self.block(child, blocks);
+                }
+            }
+            Node::Heading(heading) => {
+                let mut links = Vec::new();
+                let text = self.inline(&heading.children, &mut links);
+                let level = "#".repeat(heading.depth.min(3) as usize);
+                blocks.push(with_links(format!("{level} {text}"), links));
+            }
+            Node::Paragraph(paragraph) => {
+                let mut links = Vec::new();
+                let text = self.inline(&paragraph.children, &mut links);
+                blocks.push(with_links(text, links));
+            }
+            Node::List(list) => {
+                let mut links = Vec::new();
+                let mut items = Vec::new();
+                self.list_items(&list.children, &mut items, &mut links);
+                blocks.push(with_links(items.join("\n"), links));
+            }
+            Node::BlockQuote(quote) => {
+                let mut inner = Vec::new();
+                for child in &quote.children {
+                    self.block(child, &mut inner);
+                }
+                let quoted: Vec<String> = inner
+                    .join("\n\n")
+                    .lines()
+                    .map(|line| match line.starts_with("=>") {
+                        true => line.to_owned(),
+                        false => format!("> {line}"),
+                    })
+                    .collect();
+                blocks.push(quoted.join("\n"));
+            }
+            Node::Code(code) => {
+                let lang = code.lang.as_deref().unwrap_or_default();
+                blocks.push(format!("```{lang}\n{}\n```", code.value));
+            }
+            Node::Math(math) => blocks.push(format!("```\n{}\n```", math.value)),
+            Node::Table(table) => {
+                let mut links = Vec::new();
+                let rows: Vec<String> = table
+                    .children
+                    .iter()
+                    .map(|row| {
+                        let cells: Vec<String> = row
+                            .children()
+                            .into_iter()
+                            .flatten()
+                            .map(|cell| self.inline(cell.children().map_or(&[], |c| c), &mut links))
+                            .collect();
+                        cells.join(" | ")
+                    })
+                    .collect();
+                blocks.push(with_links(format!("```\n{}\n```", rows.join("\n")), links));
+            }
+            Node::FootnoteDefinition(footnote) => {
+                let mut inner = Vec::new();
+                for child in &footnote.children {
+                    self.block(child, &mut inner);
+                }
+                blocks.push(format!("[^{}] {}", footnote.identifier, inner.join("\n")));
+            }
+            // no rules, html, or definitions
+            _ => {}
+        }
+    }
+
+    /// Adds list items as `* ` lines, nested lists flattened.
+    fn list_items(&mut self, nodes: &[Node], items: &mut Vec<String>, links: &mut Vec<String>) {
+        for node in nodes {
+            for child in node.children().into_iter().flatten() {
+                match child {
+                    Node::List(list) => self.list_items(&list.children, items, links),
+                    Node::Paragraph(paragraph) => {
+                        let text = self.inline(&paragraph.children, links);
+                        items.push(format!("* {text}"));
+                    }
+                    block => {
+                        let mut inner = Vec::new();
+                        self.block(block, &mut inner);
+                        items.extend(inner);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The text of inline `nodes`, adding link lines for their links to
+    /// `links`.
+    fn inline(&mut self, nodes: &[Node], links: &mut Vec<String>) -> String {
+        let mut text = String::new();
+        for node in nodes {
+            match node {
+                Node::Text(t) => match self.hard_line_breaks {
+                    true => text.push_str(&t.value),
+                    false => text.push_str(&t.value.replace('\n', " ")),
+                },
+                Node::InlineCode(code) => text.push_str(&code.value),
+                Node::InlineMath(math) => text.push_str(&math.value),
+                Node::Break(_) => text.push('\n'),
+                Node::FootnoteReference(footnote) => {
+                    text.push_str(&format!("[^{}]", footnote.identifier));
+                }
+                Node::Link(link) => {
+                    let label = self.inline(&link.children, links);
+                    links.push(self.link_line(&link.url, &label));
+                    text.push_str(&label);
+                }
+                Node::LinkReference(link) => {
+                    let label = self.inline(&link.children, links);
+                    if let Some(url) = self.definitions.get(&link.identifier).cloned() {
+                        links.push(self.link_line(&url, &label));
+                    }
+                    text.push_str(&label);
+                }
+                Node::Image(image) => {
+                    if !image.url.contains("://") && !image.url.starts_with("//") {
+                        self.images.push(image.url.clone());
+                    }
+                    let alt = match image.alt.is_empty() {
+                        true => "Image",
+                        false => &image.alt,
+                    };
+                    links.push(self.link_line(&image.url, alt));
+                }
+                Node::Html(_) => {}
+                node => {
+                    let children = node.children().map_or(&[][..], |c| c);
+                    text.push_str(&self.inline(children, links));
+                }
+            }
+        }
+        text.trim().to_owned()
+    }
+
+    fn link_line(&self, url: &str, label: &str) -> String {
+        let url = match url.strip_prefix("@/") {
+            Some(path) => {
+                let (path, fragment) = path.split_once('#').unwrap_or((path, ""));
+                match self.urls.get(path) {
+                    Some(url) if fragment.is_empty() => url.clone(),
+                    Some(url) => format!("{url}#{fragment}"),
+                    None => url.to_owned(),
+                }
+            }
+            None => url.to_owned(),
+        };
+        format!("=> {url} {label}")
+    }
+}
+
+/// `text`, then its link lines. Text that's only links is left out.
+fn with_links(text: String, mut links: Vec<String>) -> String {
+    if links.is_empty() {
+        return text;
+    }
+    // autolinks inside links come out twice
+    links.dedup();
+    let links = links.join("\n");
+    match text.trim().is_empty() {
+        true => links,
+        false => format!("{text}\n{links}"),
+    }
+}
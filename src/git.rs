@@ -0,0 +1,62 @@
+//! What git knows about the site: when pages were last changed, going by
+//! history rather than file times, which clones and CI checkouts reset, and
+//! which commit is being built.
+
+use std::{collections::HashMap, path::PathBuf, process::Command};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, FixedOffset};
+
+use crate::config;
+
+/// The last commit date of every file in the content directories, by path.
+/// Each can be in a repo of its own.
+pub fn last_modified() -> Result<HashMap<PathBuf, DateTime<FixedOffset>>> {
+    let mut dates = HashMap::new();
+    for dir in &config().content_dirs {
+        let dir = dir.path();
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args([
+                "log",
+                "--format=%x00%cI",
+                "--name-only",
+                "--relative",
+                "--",
+                ".",
+            ])
+            .output()
+            .context("cannot run `git`")?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!(
+                "`git log` failed in {}: {}",
+                dir.to_string_lossy(),
+                stderr.trim()
+            );
+        }
+
+        // newest commits come first, so a file's first date is its latest
+        for commit in String::from_utf8_lossy(&output.stdout).split('\0') {
+            let mut lines = commit.lines();
+            let Some(Ok(date)) = lines.next().map(DateTime::parse_from_rfc3339) else {
+                continue;
+            };
+            for path in lines.filter(|line| !line.is_empty()) {
+                dates.entry(dir.join(path)).or_insert(date);
+            }
+        }
+    }
+    Ok(dates)
+}
+
+/// The short hash of `HEAD`, or `None` outside a git repo.
+pub fn head_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()?;
+    let commit = String::from_utf8(output.stdout).ok()?;
+    output.status.success().then(|| commit.trim().to_owned())
+}
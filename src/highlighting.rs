@@ -0,0 +1,79 @@
+//! `syntax.css`, from the `.tmTheme`s in `themes/` named under
+//! `[highlighting]`: one for light mode, and maybe another for dark.
+
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Result};
+use syntect::highlighting::{Theme, ThemeSet};
+
+use crate::{
+    config::{DarkMode, HighlightingConfig},
+    html, THEME_DIR,
+};
+
+/// Every `.tmTheme` in `themes/`, by file stem.
+fn ts() -> Result<&'static ThemeSet> {
+    static TS: OnceLock<ThemeSet> = OnceLock::new();
+    if let Some(themes) = TS.get() {
+        return Ok(themes);
+    }
+    let themes = ThemeSet::load_from_folder(&*THEME_DIR)
+        .map_err(|err| anyhow!("cannot load {}: {err}", THEME_DIR.to_string_lossy()))?;
+    Ok(TS.get_or_init(|| themes))
+}
+
+fn theme(name: &str) -> Result<&'static Theme> {
+    ts()?.themes.get(name).ok_or_else(|| {
+        anyhow!(
+            "there's no syntax theme {}",
+            THEME_DIR.join(format!("{name}.tmTheme")).to_string_lossy()
+        )
+    })
+}
+
+/// The css for `options`, or `None` if there's no `theme`.
+pub fn css(options: &HighlightingConfig) -> Result<Option<String>> {
+    let Some(name) = &options.theme else {
+        return Ok(None);
+    };
+    let mut css =
+        syntect::html::css_for_theme_with_class_style(theme(name)?, html::SYNTECT_CLASSSTYLE)?;
+    if let Some(dark) = &options.dark_theme {
+        let dark =
+            syntect::html::css_for_theme_with_class_style(theme(dark)?, html::SYNTECT_CLASSSTYLE)?;
+        match options.dark_mode {
+            DarkMode::Media => {
+                css.push_str("\n@media (prefers-color-scheme: dark) {\n");
+                css.push_str(&dark);
+                css.push_str("}\n");
+            }
+            DarkMode::DataTheme => {
+                css.push('\n');
+                css.push_str(&scoped(&dark, r#"[data-theme="dark"]"#));
+            }
+        }
+    }
+    Ok(Some(css))
+}
+
+/// `css` with `scope` in front of each of its selectors.
+fn scoped(css: &str, scope: &str) -> String {
+    // syntect starts with a comment saying which theme it is
+    let css = match css.split_once("*/") {
+        Some((_, rest)) if css.starts_with("/*") => rest,
+        _ => css,
+    };
+    let mut scoped = String::new();
+    for rule in css.split_inclusive('}') {
+        let Some((selectors, body)) = rule.split_once('{') else {
+            scoped.push_str(rule);
+            continue;
+        };
+        let selectors: Vec<String> = selectors
+            .split(',')
+            .map(|selector| format!("{scope} {}", selector.trim()))
+            .collect();
+        scoped.push_str(&format!("\n{} {{{body}", selectors.join(", ")));
+    }
+    scoped.trim_start().to_owned() + "\n"
+}
@@ -0,0 +1,25 @@
+//! Running the shell commands in `[hooks]`.
+
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use log::info;
+
+use crate::WEBSITE_DIR;
+
+/// Runs hook `name`'s `command` with `sh -c`, with `env` and
+/// `BLOG_OUTPUT_DIR` set. Its output goes straight to the terminal.
+pub fn run(name: &str, command: &str, env: &[(&str, &str)]) -> Result<()> {
+    info!("Running {name} hook");
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("BLOG_OUTPUT_DIR", &*WEBSITE_DIR)
+        .envs(env.iter().copied())
+        .status()
+        .with_context(|| format!("cannot run the {name} hook"))?;
+    if !status.success() {
+        bail!("the {name} hook failed ({status})");
+    }
+    Ok(())
+}
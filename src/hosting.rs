@@ -0,0 +1,24 @@
+//! Files that static hosts like Netlify and Cloudflare Pages read for
+//! configuration.
+
+use std::collections::BTreeMap;
+
+/// A `_redirects` file, with a permanent redirect for each (from, to) pair.
+pub fn redirects_file<'a, I: Iterator<Item = (&'a str, &'a str)>>(redirects: I) -> String {
+    redirects
+        .map(|(from, to)| format!("/{} {to} 301\n", from.trim_matches('/')))
+        .collect()
+}
+
+/// A `_headers` file, from url patterns to the headers to send for them.
+pub fn headers_file(headers: &BTreeMap<String, BTreeMap<String, String>>) -> String {
+    let mut file = String::new();
+    for (pattern, headers) in headers {
+        file.push_str(pattern);
+        file.push('\n');
+        for (name, value) in headers {
+            file.push_str(&format!("  {name}: {value}\n"));
+        }
+    }
+    file
+}
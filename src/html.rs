@@ -1,14 +1,32 @@
-use std::{collections::HashSet, path::Path};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    rc::Rc,
+};
 
-use anyhow::Result;
-use kuchikiki::{iter::Siblings, traits::TendrilSink, NodeRef};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use kuchikiki::{
+    iter::{NodeIterator, Siblings},
+    traits::TendrilSink,
+    NodeRef,
+};
+use latex2mathml::DisplayStyle;
+use log::warn;
+use sha2::{Digest, Sha384};
 use syntect::{
     html::{ClassStyle, ClassedHTMLGenerator},
     util::LinesWithEndings,
 };
 
-use crate::{ss, CONTENT_DIR};
+use crate::{config, config::ExternalLinksConfig, links, ss, staging::Staging, thumbnails};
 
+/// How highlighted code is classed, e.g. `_keyword`, to match `syntax.css`.
 pub const SYNTECT_CLASSSTYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "_" };
 
 fn get_image_dims<P: AsRef<Path>>(path: P) -> Result<imagesize::ImageSize> {
@@ -16,12 +34,29 @@ fn get_image_dims<P: AsRef<Path>>(path: P) -> Result<imagesize::ImageSize> {
     Ok(size)
 }
 
+/// Escapes `text` for html or xml, in text or a double-quoted attribute.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The top-level nodes in `document`'s body.
 pub fn get_body_children_of_document(document: &NodeRef) -> Siblings {
     document.select_first("body").unwrap().as_node().children()
 }
 
-pub fn copy_media_and_add_dimensions<P: AsRef<Path>>(document: &NodeRef, move_dir: P) {
-    let mut copied_images = HashSet::new();
+/// Returns the images, in `content_dir`, that need copying into `move_dir`,
+/// as (source, destination) pairs. Images marked `data-thumbnail` are shown
+/// as a thumbnail, which is copied too.
+pub fn find_media_and_add_dimensions<P: AsRef<Path>>(
+    document: &NodeRef,
+    content_dir: &Path,
+    move_dir: P,
+) -> Vec<(PathBuf, PathBuf)> {
+    let mut seen_images = HashSet::new();
+    let mut to_copy = Vec::new();
 
     for img_tag in document.select("img").unwrap() {
         let img_src = {
@@ -29,14 +64,18 @@ pub fn copy_media_and_add_dimensions<P: AsRef<Path>>(document: &NodeRef, move_di
             attributes.get("src").unwrap_or_default().to_owned()
         };
 
-        let img_path = CONTENT_DIR.join(&img_src);
+        // images from elsewhere stay there
+        if img_src.contains("://") || img_src.starts_with("//") {
+            continue;
+        }
+
+        let img_path = content_dir.join(&img_src);
         let img_dest = move_dir.as_ref().join(&img_src);
+        let img_dims = get_image_dims(&img_path);
 
         // avoid re-copying the same image
-        if !copied_images.contains(&img_path) {
-            std::fs::copy(&img_path, &img_dest).unwrap();
-            copied_images.insert(img_path.clone());
-            // dbg!(&img_path);
+        if seen_images.insert(img_path.clone()) {
+            to_copy.push((img_path.clone(), img_dest));
         }
 
         let mut attributes_mut = img_tag.attributes.borrow_mut();
@@ -44,13 +83,424 @@ pub fn copy_media_and_add_dimensions<P: AsRef<Path>>(document: &NodeRef, move_di
         // attributes_mut.insert("sizes", img_src.to_owned());
 
         // add image width/height attributes (prevents layout shifts)
-        if let Ok(img_dims) = get_image_dims(CONTENT_DIR.join(&img_src)) {
+        if let Ok(img_dims) = img_dims {
             attributes_mut.insert("width", img_dims.width.to_string());
             attributes_mut.insert("height", img_dims.height.to_string());
         }
+
+        // gallery images show a smaller copy, next to the full one
+        if attributes_mut.remove("data-thumbnail").is_some() {
+            match thumbnails::thumbnail(&img_path) {
+                Ok(Some((thumbnail, width, height))) => {
+                    let extension = thumbnail.extension().unwrap_or_default().to_string_lossy();
+                    let thumbnail_src =
+                        Path::new(&img_src).with_extension(format!("thumb.{extension}"));
+                    let thumbnail_src = thumbnail_src.to_string_lossy().into_owned();
+                    if seen_images.insert(thumbnail.clone()) {
+                        to_copy.push((thumbnail, move_dir.as_ref().join(&thumbnail_src)));
+                    }
+                    attributes_mut.insert("src", thumbnail_src);
+                    attributes_mut.insert("width", width.to_string());
+                    attributes_mut.insert("height", height.to_string());
+                }
+                Ok(None) => {}
+                Err(err) => warn!("cannot make a thumbnail of {img_src}: {err:#}"),
+            }
+        }
+    }
+
+    to_copy
+}
+
+/// Adds `<link rel="alternate" hreflang=...>` tags to the head for each
+/// (language, url) pair.
+pub fn add_hreflang_links(document: &NodeRef, translations: &[(String, String)]) {
+    let Ok(head) = document.select_first("head") else {
+        return;
+    };
+    for (lang, url) in translations {
+        let link = format!(
+            r#"<link rel="alternate" hreflang="{}" href="{}">"#,
+            escape(lang),
+            escape(url)
+        );
+        let link_document = kuchikiki::parse_html().one(link);
+        for node in link_document.select("link").unwrap() {
+            head.as_node().append(node.as_node().clone());
+        }
+    }
+}
+
+/// Replaces code blocks in a language that has a command in `commands` with
+/// the svg that command makes of them. Each command gets the code on stdin,
+/// and writes svg to stdout.
+///
+/// Diagrams that can't be rendered stay code blocks; returns why.
+pub fn render_diagrams(
+    document: &NodeRef,
+    commands: &HashMap<String, String>,
+) -> Vec<anyhow::Error> {
+    let mut errors = Vec::new();
+    let code_tags: Vec<_> = document.select("pre code").unwrap().collect();
+    for code_tag in code_tags {
+        let language = {
+            let attributes = code_tag.attributes.borrow();
+            let class = attributes.get("class").unwrap_or_default();
+            class
+                .strip_prefix("language-")
+                .unwrap_or_default()
+                .to_owned()
+        };
+        let Some(command) = commands.get(&language) else {
+            continue;
+        };
+
+        let svg = match run_diagram_command(command, &code_tag.text_contents()) {
+            Ok(svg) => svg,
+            Err(err) => {
+                errors.push(err.context(format!("cannot render {language} diagram")));
+                continue;
+            }
+        };
+        // an xml declaration isn't any use inside html
+        let svg = match svg.trim_start().strip_prefix("<?xml") {
+            Some(rest) => rest.split_once("?>").map_or("", |p| p.1),
+            None => &svg,
+        };
+
+        let figure = format!(r#"<figure class="diagram">{svg}</figure>"#);
+        let figure_document = kuchikiki::parse_html().one(figure);
+        let Some(pre) = code_tag.as_node().parent() else {
+            continue;
+        };
+        for node in get_body_children_of_document(&figure_document) {
+            pre.insert_before(node);
+        }
+        pre.detach();
+    }
+    errors
+}
+
+/// Replaces `$...$` and `$$...$$` math with mathml. Math that can't be
+/// rendered stays as it is; returns why.
+pub fn render_math(document: &NodeRef) -> Vec<anyhow::Error> {
+    let mut errors = Vec::new();
+    let code_tags: Vec<_> = document.select("code.language-math").unwrap().collect();
+    for code_tag in code_tags {
+        let display = {
+            let attributes = code_tag.attributes.borrow();
+            attributes
+                .get("class")
+                .unwrap_or_default()
+                .contains("math-display")
+        };
+        let (style, node) = match display {
+            // display math is a code block, replace the whole `pre`
+            true => match code_tag.as_node().parent() {
+                Some(pre) => (DisplayStyle::Block, pre),
+                None => continue,
+            },
+            false => (DisplayStyle::Inline, code_tag.as_node().clone()),
+        };
+
+        let latex = code_tag.text_contents();
+        let mathml = match latex2mathml::latex_to_mathml(&latex, style) {
+            Ok(mathml) => mathml,
+            Err(err) => {
+                errors.push(anyhow!("cannot render math `{}`: {err}", latex.trim()));
+                continue;
+            }
+        };
+
+        let math_document = kuchikiki::parse_html().one(mathml);
+        for math_node in get_body_children_of_document(&math_document) {
+            node.insert_before(math_node);
+        }
+        node.detach();
+    }
+    errors
+}
+
+/// Turns straight quotes into curly ones, `--` into an em dash, and `...`
+/// into an ellipsis, in all text but code.
+pub fn smarten_punctuation(document: &NodeRef) {
+    const SKIP: [&str; 7] = ["code", "pre", "kbd", "samp", "script", "style", "math"];
+
+    // what came before each text node decides which way its first quote goes
+    let mut prev = ' ';
+    for text in document.inclusive_descendants().text_nodes() {
+        let skip = text
+            .as_node()
+            .ancestors()
+            .elements()
+            .any(|element| SKIP.contains(&&*element.name.local));
+        if skip {
+            prev = 'x';
+            continue;
+        }
+
+        let original = text.borrow().clone();
+        let mut smart = String::with_capacity(original.len());
+        let mut chars = original.chars().peekable();
+        while let Some(c) = chars.next() {
+            let opening = prev.is_whitespace() || "([{\u{2014}".contains(prev);
+            let replacement = match c {
+                '"' if opening => '\u{201c}',
+                '"' => '\u{201d}',
+                // as in '90s
+                '\'' if opening && chars.peek().is_some_and(char::is_ascii_digit) => '\u{2019}',
+                '\'' if opening => '\u{2018}',
+                '\'' => '\u{2019}',
+                '-' if chars.peek() == Some(&'-') => {
+                    chars.next();
+                    '\u{2014}'
+                }
+                '.' if chars.peek() == Some(&'.') => {
+                    let mut rest = chars.clone();
+                    rest.next();
+                    if rest.next() == Some('.') {
+                        chars.next();
+                        chars.next();
+                        '\u{2026}'
+                    } else {
+                        '.'
+                    }
+                }
+                c => c,
+            };
+            smart.push(replacement);
+            prev = replacement;
+        }
+        *text.borrow_mut() = smart;
+    }
+}
+
+/// Joins the last two words of each of `tags` with a non-breaking space.
+pub fn prevent_widows(document: &NodeRef, tags: &[String]) {
+    if tags.is_empty() {
+        return;
+    }
+    let Ok(elements) = document.select(&tags.join(", ")) else {
+        return;
+    };
+    for element in elements {
+        // the last two words can be in different text nodes, e.g. `a <em>b</em>`
+        let texts: Vec<_> = element.as_node().descendants().text_nodes().collect();
+        let mut seen_word = false;
+        'texts: for (n, text) in texts.iter().enumerate().rev() {
+            let original = text.borrow().clone();
+            for (i, c) in original.char_indices().rev() {
+                if !c.is_whitespace() {
+                    seen_word = true;
+                    continue;
+                }
+                if !seen_word {
+                    continue;
+                }
+                let word_before = !original[..i].trim().is_empty()
+                    || texts[..n].iter().any(|t| !t.borrow().trim().is_empty());
+                if word_before {
+                    let joined =
+                        format!("{}\u{a0}{}", &original[..i], &original[i + c.len_utf8()..]);
+                    *text.borrow_mut() = joined;
+                }
+                break 'texts;
+            }
+        }
+    }
+}
+
+/// Points links like `@/20240518_what-i-ate.md#lunch`, to a file under
+/// `content/`, at that page's url, using `urls` (by path under `content/`).
+pub fn resolve_internal_links(document: &NodeRef, urls: &HashMap<String, String>) -> Result<()> {
+    for link in document.select("a[href^='@/']").unwrap() {
+        let mut attributes = link.attributes.borrow_mut();
+        let href = attributes.get("href").unwrap_or_default().to_owned();
+        let (path, fragment) = match href[2..].split_once('#') {
+            Some((path, fragment)) => (path, format!("#{fragment}")),
+            None => (&href[2..], String::new()),
+        };
+        let Some(url) = urls.get(path) else {
+            bail!("link to `{href}`, which isn't a page");
+        };
+        attributes.insert("href", config().root_relative_url(url) + fragment.as_str());
+    }
+    Ok(())
+}
+
+/// Finds whatever the html parser has to fix up in `html`, like tags closed
+/// in the wrong order or not at all. `html` can be a whole page, or just
+/// some of a body.
+pub fn parse_errors(html: &str) -> Vec<String> {
+    let errors = Rc::new(RefCell::new(Vec::new()));
+    let mut options = kuchikiki::ParseOpts::default();
+    options.tokenizer.exact_errors = true;
+    options.tree_builder.exact_errors = true;
+    options.on_parse_error = Some(Box::new({
+        let errors = Rc::clone(&errors);
+        move |error| errors.borrow_mut().push(format!("html error: {error}"))
+    }));
+
+    let is_page = html
+        .trim_start()
+        .get(..9)
+        .is_some_and(|start| start.eq_ignore_ascii_case("<!doctype"));
+    let parser = kuchikiki::parse_html_with_options(options);
+    match is_page {
+        true => parser.one(html),
+        // so the parser doesn't complain about the doctype
+        false => parser.one(format!("<!DOCTYPE html>{html}")),
+    };
+    errors.take()
+}
+
+/// Finds ids used more than once.
+pub fn duplicate_ids(document: &NodeRef) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut ids = HashSet::new();
+    for element in document.select("[id]").unwrap() {
+        let id = element
+            .attributes
+            .borrow()
+            .get("id")
+            .unwrap_or_default()
+            .to_owned();
+        if !ids.insert(id.clone()) {
+            problems.push(format!("id `{id}` is used more than once"));
+        }
     }
+    problems
 }
 
+/// Finds images without alt text, headings that skip a level, and links
+/// without any text in a page's body.
+pub fn accessibility_problems(document: &NodeRef) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for img in document.select("img").unwrap() {
+        let attributes = img.attributes.borrow();
+        if !attributes.contains("alt") {
+            let src = attributes.get("src").unwrap_or_default();
+            problems.push(format!("image `{src}` has no alt text"));
+        }
+    }
+
+    // the page's title is the h1
+    let mut last_level = 1;
+    for heading in document.select("h1, h2, h3, h4, h5, h6").unwrap() {
+        let level = heading.name.local[1..].parse().unwrap_or(1);
+        if level > last_level + 1 {
+            problems.push(format!(
+                "heading `{}` skips from h{last_level} to h{level}",
+                heading.text_contents().trim()
+            ));
+        }
+        last_level = level;
+    }
+
+    for link in document.select("a").unwrap() {
+        let has_text = !link.text_contents().trim().is_empty()
+            || link.attributes.borrow().contains("aria-label")
+            || link.as_node().select("img[alt]").unwrap().any(|img| {
+                !img.attributes
+                    .borrow()
+                    .get("alt")
+                    .unwrap_or_default()
+                    .trim()
+                    .is_empty()
+            });
+        if !has_text {
+            let href = link
+                .attributes
+                .borrow()
+                .get("href")
+                .unwrap_or_default()
+                .to_owned();
+            problems.push(format!("link to `{href}` has no text"));
+        }
+    }
+
+    problems
+}
+
+/// Adds `rel`, `target`, and `class` to links to other sites, as configured.
+pub fn mark_external_links(document: &NodeRef, options: &ExternalLinksConfig) {
+    for link in document.select("a[href]").unwrap() {
+        let mut attributes = link.attributes.borrow_mut();
+        if !config().is_external(attributes.get("href").unwrap_or_default()) {
+            continue;
+        }
+        if !options.rel.is_empty() {
+            attributes.insert("rel", options.rel.clone());
+        }
+        if options.new_tab {
+            attributes.insert("target", "_blank".to_owned());
+        }
+        if let Some(class) = &options.class {
+            let classes = match attributes.get("class") {
+                Some(classes) => format!("{classes} {class}"),
+                None => class.clone(),
+            };
+            attributes.insert("class", classes);
+        }
+    }
+}
+
+/// Adds `integrity` attributes to scripts and stylesheets on this site, so
+/// browsers can tell if they've been tampered with, e.g. on a CDN. `dir` is
+/// the directory of the page they're linked from. They're hashed as this
+/// build stages them, or as they are in the output directory if it doesn't,
+/// e.g. ones put there by hand. Ones that already have an `integrity`, or
+/// that don't exist, are left alone.
+pub fn add_integrity(document: &NodeRef, dir: &Path, staging: &Staging) {
+    for (selector, attribute) in [("script[src]", "src"), ("link[href]", "href")] {
+        for element in document.select(selector).unwrap() {
+            let mut attributes = element.attributes.borrow_mut();
+            let is_stylesheet = attributes
+                .get("rel")
+                .is_some_and(|rel| rel.split_whitespace().any(|rel| rel == "stylesheet"));
+            if attribute == "href" && !is_stylesheet || attributes.contains("integrity") {
+                continue;
+            }
+            let url = attributes.get(attribute).unwrap_or_default();
+            let Some(path) = links::link_target(url, dir) else {
+                continue;
+            };
+            let Some(contents) = staging
+                .contents(&path)
+                .or_else(|| fs::read(&path).ok().map(Cow::Owned))
+            else {
+                continue;
+            };
+            let hash = BASE64_STANDARD.encode(Sha384::digest(contents));
+            attributes.insert("integrity", format!("sha384-{hash}"));
+        }
+    }
+}
+
+fn run_diagram_command(command: &str, code: &str) -> Result<String> {
+    let mut args = command.split_whitespace();
+    let Some(program) = args.next() else {
+        bail!("the command is empty");
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("cannot run `{program}`"))?;
+    child.stdin.take().unwrap().write_all(code.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("`{command}` failed: {}", stderr.trim());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Highlights `<pre><code class="language-...">` blocks, with classes
+/// styled by `syntax.css`.
 pub fn syntax_highlight_code_blocks(document: &NodeRef) {
     for code_tag in document.select("pre code").unwrap() {
         let Some(class) = ({
@@ -94,3 +544,21 @@ pub fn syntax_highlight_code_blocks(document: &NodeRef) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_hreflang_links() {
+        let document = kuchikiki::parse_html().one("<html><head></head><body></body></html>");
+        add_hreflang_links(
+            &document,
+            &[("en\"x".to_owned(), "/a?b=1&c=\"2\"".to_owned())],
+        );
+        let link = document.select_first("link").unwrap();
+        let attributes = link.attributes.borrow();
+        assert_eq!(attributes.get("hreflang"), Some("en\"x"));
+        assert_eq!(attributes.get("href"), Some("/a?b=1&c=\"2\""));
+    }
+}
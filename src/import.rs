@@ -0,0 +1,519 @@
+//! `blog import <jekyll|hugo|zola> <dir>`: moves another generator's posts
+//! into `content/`.
+//!
+//! Front matter keys are mapped to ours (anything unknown is kept, for
+//! templates to use as `extra`), each page's old url becomes an alias if it's
+//! changed, and images are copied next to the posts using them.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, NaiveDate};
+use gray_matter::{
+    engine::{Engine, YAML},
+    Matter,
+};
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+use regex::Regex;
+use serde_json::{Map, Value};
+use walkdir::WalkDir;
+
+use crate::{config, parse_date, CONTENT_DIR, PAGES_DIR};
+
+lazy_static! {
+    /// `2024-05-18-` at the start of a file name.
+    static ref DATE_PREFIX_RE: Regex = Regex::new(r"^(\d{4}-\d{2}-\d{2})[-_]").unwrap();
+    /// Images in markdown or html, and links to other markdown files.
+    static ref IMAGE_RE: Regex =
+        Regex::new(r#"(!\[[^\]]*\]\(\s*)((?:\{\{[^}]*\}\})?[^)\s]+)|(<img\s[^>]*?src=")([^"]+)"#).unwrap();
+    static ref INTERNAL_LINK_RE: Regex = Regex::new(r"\]\(@/([^)#\s]+)").unwrap();
+    /// Liquid, e.g. `{{ site.baseurl }}`, in front of an image path.
+    static ref LIQUID_RE: Regex = Regex::new(r"^\{\{[^}]*\}\}").unwrap();
+}
+
+/// Where posts are imported from.
+#[derive(Clone, Copy, PartialEq)]
+enum Generator {
+    Jekyll,
+    Hugo,
+    Zola,
+}
+
+/// A page on its way from the other generator.
+struct Import {
+    source: PathBuf,
+    /// Where the page's own files are, for relative image paths.
+    source_dir: PathBuf,
+    /// The file it's written to.
+    path: PathBuf,
+    slug: String,
+    front_matter: Map<String, Value>,
+    contents: String,
+}
+
+/// Imports the posts and pages of the site in `dir`, made with `generator`.
+pub fn run(generator: &str, dir: &Path) -> Result<()> {
+    let generator = match generator {
+        "jekyll" => Generator::Jekyll,
+        "hugo" => Generator::Hugo,
+        "zola" => Generator::Zola,
+        _ => bail!("can't import from `{generator}`, only jekyll, hugo, or zola"),
+    };
+    let (content_dir, static_dir) = match generator {
+        Generator::Jekyll => (dir.to_owned(), dir.to_owned()),
+        Generator::Hugo | Generator::Zola => (dir.join("content"), dir.join("static")),
+    };
+    let patterns = permalink_patterns(generator, dir)?;
+
+    let mut imports = Vec::new();
+    for entry in WalkDir::new(&content_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let source = entry.into_path();
+        let relative = source.strip_prefix(&content_dir)?.to_owned();
+        if !source.is_file() || source.extension().is_none_or(|e| e != "md") {
+            continue;
+        }
+        if is_skipped(generator, &relative) {
+            debug!("{} isn't a page, skipping", source.to_string_lossy());
+            continue;
+        }
+        let import = read_page(generator, &source, &relative, &patterns)
+            .with_context(|| format!("cannot import {}", source.to_string_lossy()))?;
+        imports.push((relative, import));
+    }
+
+    // for zola's `@/` links, which are by path under `content/`
+    let new_paths: HashMap<String, String> = imports
+        .iter()
+        .filter_map(|(relative, import)| {
+            let path = import.path.strip_prefix(&*CONTENT_DIR).ok()?;
+            Some((
+                relative.to_string_lossy().replace('\\', "/"),
+                path.to_string_lossy().replace('\\', "/"),
+            ))
+        })
+        .collect();
+
+    // everything's checked before anything's written, so a failed import
+    // doesn't leave half of itself behind
+    let mut planned = Vec::new();
+    let mut targets = HashMap::new();
+    for (_, mut import) in imports {
+        import.contents = INTERNAL_LINK_RE
+            .replace_all(&import.contents, |caps: &regex::Captures| {
+                match new_paths.get(&caps[1]) {
+                    Some(path) => format!("](@/{path}"),
+                    None => caps[0].to_owned(),
+                }
+            })
+            .into_owned();
+        let copies = image_copies(&mut import, &static_dir);
+        for target in std::iter::once(&import.path).chain(copies.iter().map(|(_, to)| to)) {
+            if target.exists() {
+                bail!(
+                    "{} already exists, not importing {}",
+                    target.to_string_lossy(),
+                    import.source.to_string_lossy()
+                );
+            }
+            if let Some(other) = targets.insert(target.clone(), import.source.clone()) {
+                bail!(
+                    "{} and {} would both be imported as {}",
+                    other.to_string_lossy(),
+                    import.source.to_string_lossy(),
+                    target.to_string_lossy()
+                );
+            }
+        }
+        planned.push((import, copies));
+    }
+
+    for (import, copies) in planned {
+        for (from, to) in copies {
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&from, &to)
+                .with_context(|| format!("cannot copy {}", from.to_string_lossy()))?;
+        }
+        if let Some(parent) = import.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(
+            &import.path,
+            page_file(&import.front_matter, &import.contents),
+        )?;
+        info!(
+            "Writing {} (from {})",
+            import.path.to_string_lossy(),
+            import.source.to_string_lossy()
+        );
+    }
+    Ok(())
+}
+
+/// Whether the markdown file at `relative` (in the content directory) isn't
+/// a page, like a section index or a readme.
+fn is_skipped(generator: Generator, relative: &Path) -> bool {
+    let name = relative.file_name().unwrap_or_default();
+    if name == "_index.md" {
+        return true;
+    }
+    match generator {
+        // posts, drafts, and pages at the top, not includes, layouts, etc
+        Generator::Jekyll => {
+            let in_posts = relative.starts_with("_posts") || relative.starts_with("_drafts");
+            let is_page =
+                relative.components().count() == 1 && name != "index.md" && name != "README.md";
+            !in_posts && !is_page
+        }
+        Generator::Hugo | Generator::Zola => false,
+    }
+}
+
+/// Reads the page at `source`, and works out where it goes.
+fn read_page(
+    generator: Generator,
+    source: &Path,
+    relative: &Path,
+    patterns: &HashMap<String, String>,
+) -> Result<Import> {
+    let file_contents = fs::read_to_string(source)?;
+    let (mut old, contents) = split_front_matter(&file_contents)?;
+
+    let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+    // bundles are a directory with an `index.md`, and their files
+    let is_bundle = stem == "index" && relative.components().count() > 1;
+    let name = match is_bundle {
+        true => relative
+            .parent()
+            .and_then(|dir| dir.file_name())
+            .unwrap_or_default()
+            .to_string_lossy(),
+        false => stem,
+    };
+    let name_date = DATE_PREFIX_RE
+        .captures(&name)
+        .and_then(|caps| NaiveDate::parse_from_str(&caps[1], "%Y-%m-%d").ok());
+    let name = DATE_PREFIX_RE.replace(&name, "").into_owned();
+
+    let title = old
+        .remove("title")
+        .and_then(|t| t.as_str().map(str::to_owned));
+    let title = title.unwrap_or_else(|| name.clone());
+    let slug = match old.remove("slug") {
+        Some(Value::String(slug)) => slug,
+        _ => name.clone(),
+    };
+    let date_value = old.remove("date");
+    let date = date_value
+        .as_ref()
+        .and_then(Value::as_str)
+        .and_then(parse_date)
+        .or(name_date);
+
+    let mut front_matter = Map::new();
+    front_matter.insert("title".into(), title.clone().into());
+    // the file name has the date too, but the front matter's might be how
+    // it's shown
+    if let Some(date) = date_value.filter(|date| date.as_str().and_then(parse_date).is_some()) {
+        front_matter.insert("date".into(), date);
+    }
+
+    let in_drafts = relative.starts_with("_drafts");
+    let unpublished = old.remove("published") == Some(Value::Bool(false));
+    let draft = old.remove("draft") == Some(Value::Bool(true));
+    if in_drafts || unpublished || draft {
+        front_matter.insert("draft".into(), true.into());
+    }
+
+    let mut tags = Vec::new();
+    if let Some(Value::Object(mut taxonomies)) = old.remove("taxonomies") {
+        tags.extend(taxonomies.remove("tags").into_iter().flat_map(list));
+    }
+    tags.extend(old.remove("tags").into_iter().flat_map(list));
+    if !tags.is_empty() {
+        front_matter.insert("tags".into(), tags.into());
+    }
+
+    let mut aliases: Vec<Value> = ["aliases", "redirect_from"]
+        .iter()
+        .filter_map(|key| old.remove(*key))
+        .flat_map(list)
+        .collect();
+    let old_url = old_url(generator, relative, &slug, &title, date, &mut old, patterns);
+    let standalone = date.is_none();
+    let new_url = match standalone {
+        true => format!("/{slug}/"),
+        false => config().permalink(&slug, date)?,
+    };
+    // drafts were never anywhere
+    if !in_drafts && old_url.trim_end_matches("index.html") != new_url {
+        aliases.push(old_url.into());
+    }
+    if !aliases.is_empty() {
+        front_matter.insert("aliases".into(), aliases.into());
+    }
+
+    // zola's `[extra]` is our front matter's extra already
+    if let Some(Value::Object(extra)) = old.remove("extra") {
+        old.extend(extra);
+    }
+    for key in ["layout", "template", "categories", "category", "permalink"] {
+        old.remove(key);
+    }
+    front_matter.extend(old);
+
+    let path = match date {
+        Some(date) => CONTENT_DIR.join(format!("{}_{slug}.md", date.format("%Y%m%d"))),
+        None => PAGES_DIR.join(format!("{slug}.md")),
+    };
+
+    Ok(Import {
+        source: source.to_owned(),
+        source_dir: source.parent().unwrap_or(Path::new("")).to_owned(),
+        path,
+        slug,
+        front_matter,
+        contents,
+    })
+}
+
+/// Splits a file into its front matter, YAML between `---`s or TOML between
+/// `+++`s, and its markdown.
+fn split_front_matter(file_contents: &str) -> Result<(Map<String, Value>, String)> {
+    if let Some(rest) = file_contents.strip_prefix("+++") {
+        let Some((toml, contents)) = rest.split_once("\n+++") else {
+            bail!("front matter isn't closed with `+++`");
+        };
+        let Value::Object(front_matter) = toml_to_json(toml::from_str(toml)?) else {
+            bail!("front matter isn't a table");
+        };
+        let contents = contents.trim_start_matches(['\r', '\n']).to_owned();
+        return Ok((front_matter, contents));
+    }
+
+    let result = Matter::<YAML>::new().parse(file_contents);
+    let front_matter = match result.data.map(|d| d.deserialize()).transpose()? {
+        Some(Value::Object(front_matter)) => front_matter,
+        _ => Map::new(),
+    };
+    Ok((front_matter, result.content))
+}
+
+/// Converts TOML to JSON, with dates as strings.
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => s.into(),
+        toml::Value::Integer(i) => i.into(),
+        toml::Value::Float(f) => f.into(),
+        toml::Value::Boolean(b) => b.into(),
+        toml::Value::Datetime(d) => d.to_string().into(),
+        toml::Value::Array(items) => items.into_iter().map(toml_to_json).collect(),
+        toml::Value::Table(table) => table
+            .into_iter()
+            .map(|(key, value)| (key, toml_to_json(value)))
+            .collect(),
+    }
+}
+
+/// A front matter value that should be a list, but might be one item, or a
+/// string of items separated by spaces (like jekyll's categories).
+fn list(value: Value) -> Vec<Value> {
+    match value {
+        Value::Array(items) => items,
+        Value::String(items) => items.split_whitespace().map(Value::from).collect(),
+        Value::Null => Vec::new(),
+        item => vec![item],
+    }
+}
+
+/// Permalink patterns from the other generator's config, by section (`""`
+/// for all of them).
+fn permalink_patterns(generator: Generator, dir: &Path) -> Result<HashMap<String, String>> {
+    let mut patterns = HashMap::new();
+    match generator {
+        Generator::Jekyll => {
+            // without a config, it's jekyll's default, `date`
+            let config: Value = match fs::read_to_string(dir.join("_config.yml")) {
+                Ok(contents) => YAML::parse(&contents).deserialize()?,
+                Err(_) => Value::Null,
+            };
+            let pattern = match config["permalink"].as_str() {
+                Some("date") | None => "/:categories/:year/:month/:day/:title.html",
+                Some("pretty") => "/:categories/:year/:month/:day/:title/",
+                Some("ordinal") => "/:categories/:year/:y_day/:title.html",
+                Some("none") => "/:categories/:title.html",
+                Some(pattern) => pattern,
+            };
+            patterns.insert(String::new(), pattern.to_owned());
+        }
+        Generator::Hugo => {
+            let Some(contents) = ["hugo.toml", "config.toml"]
+                .iter()
+                .find_map(|name| fs::read_to_string(dir.join(name)).ok())
+            else {
+                return Ok(patterns);
+            };
+            let config: toml::Value = toml::from_str(&contents)?;
+            if let Some(permalinks) = config.get("permalinks").and_then(|p| p.as_table()) {
+                for (section, pattern) in permalinks {
+                    if let Some(pattern) = pattern.as_str() {
+                        patterns.insert(section.clone(), pattern.to_owned());
+                    }
+                }
+            }
+        }
+        // zola's urls come from the path, unless a page says otherwise
+        Generator::Zola => {}
+    }
+    Ok(patterns)
+}
+
+/// Works out the url the page had, taking and using whatever front matter
+/// says it should be.
+fn old_url(
+    generator: Generator,
+    relative: &Path,
+    slug: &str,
+    title: &str,
+    date: Option<NaiveDate>,
+    front_matter: &mut Map<String, Value>,
+    patterns: &HashMap<String, String>,
+) -> String {
+    let explicit = ["permalink", "url", "path"]
+        .iter()
+        .find_map(|key| front_matter.get(*key)?.as_str().map(str::to_owned));
+    if let Some(url) = explicit {
+        front_matter.remove("url");
+        front_matter.remove("path");
+        return format!("/{}", url.trim_start_matches('/'));
+    }
+
+    // the directories the page is in, not counting a bundle's own
+    let mut dirs: Vec<String> = relative
+        .parent()
+        .unwrap_or(Path::new(""))
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    if relative.file_stem().is_some_and(|stem| stem == "index") {
+        dirs.pop();
+    }
+    let section = dirs.first().cloned().unwrap_or_default();
+
+    let categories: Vec<String> = ["categories", "category"]
+        .iter()
+        .filter_map(|key| front_matter.get(*key).cloned())
+        .flat_map(list)
+        .filter_map(|c| c.as_str().map(|c| c.to_lowercase()))
+        .collect();
+
+    let pattern = match generator {
+        Generator::Jekyll if !relative.starts_with("_posts") => "/:title.html",
+        Generator::Jekyll => &patterns[""],
+        Generator::Hugo => match patterns.get(&section) {
+            Some(pattern) => pattern,
+            None => return join_url(dirs.iter().map(String::as_str).chain([slug])),
+        },
+        Generator::Zola => return join_url(dirs.iter().map(String::as_str).chain([slug])),
+    };
+
+    let mut url = pattern
+        .replace(":categories", &categories.join("/"))
+        .replace(":section", &section)
+        .replace(":slug", slug);
+    url = match generator {
+        Generator::Hugo => url.replace(":title", &slug::slugify(title)),
+        _ => url.replace(":title", slug),
+    };
+    if let Some(date) = date {
+        url = url
+            .replace(":year", &date.year().to_string())
+            .replace(":month", &format!("{:02}", date.month()))
+            .replace(":i_month", &date.month().to_string())
+            .replace(":day", &format!("{:02}", date.day()))
+            .replace(":i_day", &date.day().to_string())
+            .replace(":y_day", &format!("{:03}", date.ordinal()));
+    }
+    // an empty `:categories` leaves a `//`
+    let segments = url.split('/').filter(|s| !s.is_empty()).collect::<Vec<_>>();
+    let trailing_slash = url.ends_with('/');
+    let mut url = format!("/{}", segments.join("/"));
+    if trailing_slash && url != "/" {
+        url.push('/');
+    }
+    url
+}
+
+fn join_url<'a>(segments: impl Iterator<Item = &'a str>) -> String {
+    let mut url = String::from("/");
+    for segment in segments.filter(|s| !s.is_empty()) {
+        url.push_str(segment);
+        url.push('/');
+    }
+    url
+}
+
+/// Points `import` at copies of the images it uses in `content/<slug>/`,
+/// returning what to copy where. Absolute paths are in `static_dir`,
+/// relative ones are next to the page.
+fn image_copies(import: &mut Import, static_dir: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let mut copies = Vec::new();
+    let contents = IMAGE_RE.replace_all(&import.contents, |caps: &regex::Captures| {
+        let (prefix, src) = match caps.get(1) {
+            Some(prefix) => (prefix.as_str(), &caps[2]),
+            None => (&caps[3], &caps[4]),
+        };
+        let src_path = LIQUID_RE.replace(src, "");
+        if src_path.contains("://") || src_path.starts_with("//") || src_path.starts_with("data:") {
+            return caps[0].to_owned();
+        }
+        let from = match src_path.strip_prefix('/') {
+            Some(path) => static_dir.join(path),
+            None => import.source_dir.join(&*src_path),
+        };
+        let Some(file_name) = from.file_name().filter(|_| from.is_file()) else {
+            warn!(
+                "{}: cannot find image `{src}`",
+                import.source.to_string_lossy()
+            );
+            return caps[0].to_owned();
+        };
+        let to = PathBuf::from(&import.slug).join(file_name);
+        copies.push((from.clone(), CONTENT_DIR.join(&to)));
+        format!("{prefix}{}", to.to_string_lossy().replace('\\', "/"))
+    });
+    import.contents = contents.into_owned();
+    // the same image used twice is copied once
+    copies.sort();
+    copies.dedup();
+    copies
+}
+
+/// A page's file, with its front matter as YAML, title and date first.
+/// Values are written as JSON, which YAML reads too.
+fn page_file(front_matter: &Map<String, Value>, contents: &str) -> String {
+    let mut keys: Vec<&String> = front_matter.keys().collect();
+    keys.sort_by_key(|key| match key.as_str() {
+        "title" => 0,
+        "date" => 1,
+        _ => 2,
+    });
+
+    let mut file = String::from("---\n");
+    for key in keys {
+        file.push_str(&format!("{key}: {}\n", front_matter[key]));
+    }
+    file.push_str("---\n\n");
+    file.push_str(contents.trim());
+    file.push('\n');
+    file
+}
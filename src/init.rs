@@ -0,0 +1,128 @@
+//! `blog init <dir>`: a new site, with everything a build needs.
+
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use log::info;
+
+use crate::{CONFIG_PATH, CONTENT_DIR, TEMPLATE_DIR, THEME_DIR, WEBSITE_DIR};
+
+const CONFIG: &str = r#"title = "My blog"
+base_url = "https://example.com"
+author = "Me"
+language = "en"
+permalink = "/{slug}/"
+"#;
+
+const BASE_TEMPLATE: &str = r#"<!DOCTYPE html>
+
+<html lang="{{ lang | default(value=site.language) }}">
+
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width,initial-scale=1.0">
+  <title>{% if title %}{{ title }} - {% endif %}{{ site.title }}</title>
+</head>
+
+<body>
+  <main>
+    {% block content %}{% endblock content %}
+  </main>
+</body>
+
+</html>
+"#;
+
+const INDEX_TEMPLATE: &str = r#"{% extends "base.html" %}
+{% block content -%}
+    <h1>{{ site.title }}</h1>
+    {%- for post in posts %}
+    <p><a href="{{ post.url | url_for }}">{{ post.title }}</a> - {{ post.date }}</p>
+    {%- endfor %}
+{%- endblock content %}
+"#;
+
+const PAGE_TEMPLATE: &str = r#"{% extends "base.html" %}
+{% block content -%}
+    <h1>{{ title }}</h1>
+    <p>{{ date }}</p>
+    {{ contents }}
+    <p><a href="{{ url_for(path="") }}">Go back</a></p>
+{%- endblock content %}
+"#;
+
+const NOT_FOUND_TEMPLATE: &str = r#"{% extends "base.html" %}
+{% block content -%}
+    <h1>Not found</h1>
+    <p>There's nothing here. <a href="{{ url_for(path="") }}">Go back</a></p>
+{%- endblock content %}
+"#;
+
+const REDIRECT_TEMPLATE: &str = r#"<!DOCTYPE html>
+
+<html lang="en">
+
+<head>
+  <meta charset="utf-8">
+  <title>Redirecting…</title>
+  <link rel="canonical" href="{{ url | url_for(absolute=true) }}">
+  <meta http-equiv="refresh" content="0; url={{ url | url_for }}">
+</head>
+
+<body>
+  <a href="{{ url | url_for }}">This page has moved.</a>
+</body>
+
+</html>
+"#;
+
+const POST: &str = "---
+title: Hello, world
+---
+
+This is the first post. Posts go in `content/`, named like
+`20240518_hello-world.md`, and the date in the name is the post's date.
+";
+
+/// A syntax highlighting theme to start with.
+const THEME: &str = include_str!("../themes/ayu-light.tmTheme");
+
+/// Creates a new site in `dir`, which has to be empty if it exists.
+pub fn run(dir: &Path) -> Result<()> {
+    if dir
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        bail!("{} isn't empty", dir.to_string_lossy());
+    }
+
+    let post = format!("{}_hello-world.md", Local::now().format("%Y%m%d"));
+    let files = [
+        (CONFIG_PATH.clone(), CONFIG),
+        (TEMPLATE_DIR.join("base.html"), BASE_TEMPLATE),
+        (TEMPLATE_DIR.join("index.html"), INDEX_TEMPLATE),
+        (TEMPLATE_DIR.join("page.html"), PAGE_TEMPLATE),
+        (TEMPLATE_DIR.join("404.html"), NOT_FOUND_TEMPLATE),
+        (TEMPLATE_DIR.join("redirect.html"), REDIRECT_TEMPLATE),
+        (THEME_DIR.join("ayu-light.tmTheme"), THEME),
+        (CONTENT_DIR.join(post), POST),
+    ];
+    for (path, contents) in files {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, contents)
+            .with_context(|| format!("cannot write {}", path.to_string_lossy()))?;
+        info!("Writing {}", path.to_string_lossy());
+    }
+    fs::create_dir_all(dir.join(&*WEBSITE_DIR))?;
+
+    info!(
+        "Run `blog` in {} to build the site into {}",
+        dir.to_string_lossy(),
+        WEBSITE_DIR.to_string_lossy()
+    );
+    Ok(())
+}
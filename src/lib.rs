@@ -0,0 +1,2051 @@
+//! A static site generator for a blog: markdown pages in `content/`,
+//! rendered through tera templates into `website/`.
+//!
+//! `blog` the binary is a thin wrapper around [`run`]. Other programs can
+//! drive the same builds with [`build`], read pages without building with
+//! [`read_pages`], and use the [`html`] utilities and the build [`state`]
+//! directly.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs,
+    io::{self, IsTerminal, Write},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock, RwLock},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Datelike, Local, NaiveDate, SecondsFormat};
+use gray_matter::{engine::YAML, Matter};
+use kuchikiki::traits::TendrilSink;
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tera::Tera;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::{
+    cache::RenderCache,
+    cli::{Args, Command},
+    config::{AuthorConfig, Config, CspMode, MarkdownConfig, SortBy, TypeConfig},
+    previews::PreviewCache,
+    staging::Staging,
+    state::State,
+    timings::Phase,
+};
+
+mod activitypub;
+mod archetypes;
+mod blogroll;
+mod cache;
+mod checksum;
+pub mod cli;
+mod compress;
+pub mod config;
+mod csp;
+mod data;
+mod deploy;
+mod doctor;
+mod email;
+mod epub;
+mod favicons;
+mod feeds;
+mod filters;
+mod functions;
+mod gemini;
+mod git;
+mod highlighting;
+mod hooks;
+mod hosting;
+pub mod html;
+mod import;
+mod init;
+mod links;
+mod lock;
+pub mod logging;
+mod microformats;
+mod plain_text;
+pub mod plugins;
+mod previews;
+mod schema;
+mod shortcodes;
+mod staging;
+pub mod state;
+mod stats;
+mod themes;
+mod thumbnails;
+mod timings;
+mod webmentions;
+
+lazy_static! {
+    static ref CACHE_DIR: PathBuf = ".cache".into();
+    static ref CONFIG_PATH: PathBuf = "config.toml".into();
+    static ref CONTENT_DIR: PathBuf = "content".into();
+    static ref DATA_DIR: PathBuf = "data".into();
+    static ref PAGES_DIR: PathBuf = CONTENT_DIR.join("pages");
+    /// Copied as it is to `website/.well-known/`, e.g. `security.txt`.
+    static ref WELL_KNOWN_DIR: PathBuf = CONTENT_DIR.join(".well-known");
+    static ref TEMPLATE_DIR: PathBuf = "templates".into();
+    static ref THEME_DIR: PathBuf = "themes".into();
+    /// Plugins compiled to WebAssembly.
+    static ref PLUGIN_DIR: PathBuf = "plugins".into();
+    /// `.sublime-syntax` files for languages syntect doesn't know.
+    static ref SYNTAX_DIR: PathBuf = "syntaxes".into();
+    static ref WEBSITE_DIR: PathBuf = "website".into();
+    static ref CAPSULE_DIR: PathBuf = "capsule".into();
+    static ref EMAIL_DIR: PathBuf = "email".into();
+    static ref STATE_PATH: PathBuf = CACHE_DIR.join("state.json");
+    static ref LOCK_PATH: PathBuf = CACHE_DIR.join("build.lock");
+}
+
+/// Everything a build goes by. [`build`] and [`read_pages`] load a new one
+/// each time, so that a process can build more than once; the rest is
+/// filled in as the build gets to it.
+struct Site {
+    config: Arc<Config>,
+    /// The directories of the theme and those it extends, most specific
+    /// first.
+    themes: Vec<PathBuf>,
+    tera: Arc<Tera>,
+    build: BuildContext,
+    /// Everything in `data/`.
+    data: OnceLock<HashMap<String, tera::Value>>,
+    /// All pages being built, keyed by slug.
+    pages: OnceLock<HashMap<String, PageSummary>>,
+    /// Every tag's feed, if they're turned on.
+    feeds: OnceLock<Vec<feeds::Feed>>,
+    /// Where the favicons made from `[favicon]` are, if any.
+    favicons: OnceLock<favicons::Favicons>,
+    /// Link previews, loaded from the cache when the first one is needed.
+    previews: OnceLock<Mutex<PreviewCache>>,
+    /// Symlinks back to a directory they're in, which have been warned about.
+    loops: Mutex<HashSet<PathBuf>>,
+}
+
+impl Site {
+    /// Loads the templates of the site and its theme, for `config`.
+    fn new(config: Config) -> Result<Site> {
+        let themes = match &config.theme {
+            Some(theme) => themes::chain(theme)?,
+            None => Vec::new(),
+        };
+        Ok(Site {
+            config: Arc::new(config),
+            tera: Arc::new(make_tera(&themes)?),
+            themes,
+            build: BuildContext::new(),
+            data: OnceLock::new(),
+            pages: OnceLock::new(),
+            feeds: OnceLock::new(),
+            favicons: OnceLock::new(),
+            previews: OnceLock::new(),
+            loops: Mutex::default(),
+        })
+    }
+}
+
+/// The site being built.
+static SITE: RwLock<Option<Arc<Site>>> = RwLock::new(None);
+
+/// The site being built. Panics if it isn't loaded yet, which [`run`],
+/// [`build`], and [`read_pages`] do first.
+fn site() -> Arc<Site> {
+    let site = SITE.read().unwrap();
+    Arc::clone(site.as_ref().expect("the site is loaded at startup"))
+}
+
+/// The site config. Panics if it isn't loaded yet, as [`site`] does.
+pub fn config() -> Arc<Config> {
+    Arc::clone(&site().config)
+}
+
+/// Loads the config, with `args.env`'s overlay, and everything that goes with
+/// it, in place of whatever the last build used.
+fn load_config(args: &Args) -> Result<()> {
+    let site = Site::new(Config::from_file(&*CONFIG_PATH, args.env.as_deref())?)?;
+    *SITE.write().unwrap() = Some(Arc::new(site));
+    Ok(())
+}
+
+/// Sets up a site with the default config, for tests.
+#[cfg(test)]
+fn load_default_config() {
+    let mut loaded = SITE.write().unwrap();
+    if loaded.is_none() {
+        *loaded = Some(Arc::new(Site::new(Config::default()).unwrap()));
+    }
+}
+
+/// Calls `f` with the link previews, loaded from the cache when the first
+/// one is needed.
+fn with_previews<T>(f: impl FnOnce(&mut PreviewCache) -> T) -> T {
+    let site = site();
+    let previews = site
+        .previews
+        .get_or_init(|| Mutex::new(PreviewCache::load(CACHE_DIR.join("previews.json"))));
+    let result = f(&mut previews.lock().unwrap());
+    result
+}
+
+/// The site's templates, with those of `themes`, the theme and the ones it
+/// extends, for any it doesn't have itself.
+fn load_templates(themes: &[PathBuf]) -> tera::Result<Tera> {
+    // by name, from the first directory that has it
+    let mut files = BTreeMap::new();
+    let dirs =
+        std::iter::once(TEMPLATE_DIR.clone()).chain(themes.iter().map(|t| t.join("templates")));
+    for dir in dirs {
+        for entry in WalkDir::new(&dir).into_iter().filter_map(|e| e.ok()) {
+            let path = entry.into_path();
+            if path.is_file() && path.extension().is_some_and(|e| e == "html" || e == "md") {
+                let name = path.strip_prefix(&dir).unwrap_or(&path);
+                let name = name.to_string_lossy().replace('\\', "/");
+                files.entry(name).or_insert(path);
+            }
+        }
+    }
+
+    let mut tera = Tera::default();
+    tera.add_template_files(
+        files
+            .into_iter()
+            .map(|(name, path)| (path, Some(name)))
+            .collect::<Vec<_>>(),
+    )?;
+    Ok(tera)
+}
+
+/// The site's templates, with the filters and functions they can use.
+/// Panics if they aren't loaded yet, as [`site`] does.
+fn tera() -> Arc<Tera> {
+    Arc::clone(&site().tera)
+}
+
+fn make_tera(themes: &[PathBuf]) -> Result<Tera> {
+    let mut tera = load_templates(themes)?;
+    // don't autoescape anything
+    tera.autoescape_on(vec![]);
+    // `slugify` comes with tera's builtins
+    tera.register_filter("date", filters::date);
+    tera.register_filter("truncate_words", filters::truncate_words);
+    tera.register_filter("markdown", filters::markdown);
+    tera.register_filter("url_for", filters::url_for);
+    tera.register_function("get_page", functions::get_page);
+    tera.register_function("url_for", functions::url_for);
+    filters::register_scripts(&mut tera, themes)?;
+    Ok(tera)
+}
+
+/// Syntect's syntaxes, and those in `syntaxes/`.
+pub fn ss() -> &'static syntect::parsing::SyntaxSet {
+    static PS: OnceLock<syntect::parsing::SyntaxSet> = OnceLock::new();
+    PS.get_or_init(|| {
+        let defaults = syntect::parsing::SyntaxSet::load_defaults_newlines();
+        if !SYNTAX_DIR.is_dir() {
+            return defaults;
+        }
+        let mut builder = defaults.clone().into_builder();
+        match builder.add_from_folder(&*SYNTAX_DIR, true) {
+            Ok(()) => builder.build(),
+            Err(err) => {
+                warn!("cannot load {}: {err}", SYNTAX_DIR.to_string_lossy());
+                defaults
+            }
+        }
+    })
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct FrontMatter {
+    title: String,
+    #[serde(default)]
+    date: String,
+    slug: Option<String>,
+    #[serde(default)]
+    draft: bool,
+    /// Old paths that should redirect here.
+    #[serde(default)]
+    aliases: Vec<String>,
+    /// When the page stops being listed.
+    expires: Option<String>,
+    /// Listed before everything else in the index.
+    #[serde(default)]
+    pinned: bool,
+    /// Orders pages with the same date (or no date), lightest first.
+    weight: Option<i64>,
+    /// The name of the series this page is a part of.
+    series: Option<String>,
+    /// What kind of page this is, e.g. `note` or `photo`. Picks the template
+    /// and whether it's in the index.
+    #[serde(rename = "type", default = "default_page_type")]
+    page_type: String,
+    /// Ids of whoever wrote the page, from `[authors]` in the config.
+    author: Option<String>,
+    #[serde(default)]
+    authors: Vec<String>,
+    /// Defaults to the site's `language`.
+    lang: Option<String>,
+    /// Pages with the same key are translations of each other.
+    translation_key: Option<String>,
+    /// Changes to the site's `[markdown]` options, just for this page.
+    #[serde(default)]
+    markdown: FrontMatterValues,
+    /// Stylesheets and scripts just for this page. Ones in `content/` are
+    /// copied next to it, with a hash of their contents in their names.
+    #[serde(default)]
+    extra_css: Vec<String>,
+    #[serde(default)]
+    extra_js: Vec<String>,
+    /// Anything else, for templates to use as `extra`.
+    #[serde(flatten)]
+    extra: HashMap<String, tera::Value>,
+}
+
+fn default_page_type() -> String {
+    "post".to_owned()
+}
+
+/// What the markdown parser looks for, going by `options`.
+fn parse_options(options: &MarkdownConfig) -> markdown::ParseOptions {
+    let mut parse = markdown::ParseOptions::gfm();
+    parse.constructs.gfm_footnote_definition = options.footnotes;
+    parse.constructs.gfm_label_start_footnote = options.footnotes;
+    parse.constructs.gfm_table = options.tables;
+    parse.constructs.gfm_strikethrough = options.strikethrough;
+    parse.constructs.math_flow = options.math;
+    parse.constructs.math_text = options.math;
+    parse
+}
+
+fn markdown_to_html(contents: &str, options: &MarkdownConfig) -> String {
+    let markdown_options = markdown::Options {
+        parse: parse_options(options),
+        compile: markdown::CompileOptions {
+            allow_dangerous_html: options.allow_dangerous_html,
+            allow_dangerous_protocol: true,
+            ..markdown::CompileOptions::gfm()
+        },
+    };
+
+    let contents = match options.hard_line_breaks {
+        true => with_hard_line_breaks(contents),
+        false => contents.to_owned(),
+    };
+    markdown::to_html_with_options(&contents, &markdown_options).unwrap()
+}
+
+/// Ends every line outside code blocks with two spaces, which markdown
+/// takes as a hard line break.
+fn with_hard_line_breaks(contents: &str) -> String {
+    let mut fence: Option<&str> = None;
+    let mut output = String::with_capacity(contents.len());
+    for line in contents.lines() {
+        let trimmed = line.trim_start();
+        let marker = ["```", "~~~", "$$"]
+            .into_iter()
+            .find(|marker| trimmed.starts_with(marker));
+        match (fence, marker) {
+            (None, Some(marker)) => fence = Some(marker),
+            (Some(open), Some(marker)) if open == marker => fence = None,
+            (None, None) if !trimmed.is_empty() => {
+                output.push_str(line.trim_end());
+                output.push_str("  \n");
+                continue;
+            }
+            _ => {}
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    output
+}
+
+/// A post read from `content/`, not yet rendered.
+pub struct Page {
+    /// Where the page came from, e.g. `content/20240518_about-this-blog.md`.
+    pub path: PathBuf,
+    /// The directory in `content_dirs` it's in, which its images and other
+    /// files are relative to.
+    pub root: PathBuf,
+    front_matter: FrontMatter,
+    pub slug: String,
+    /// Where the page ends up, e.g. `/about-this-blog/`.
+    pub url: String,
+    pub date: Option<NaiveDate>,
+    /// Not a post, so left out of the index, e.g. `/about/`.
+    pub standalone: bool,
+    /// Past its `expires` date, so left out of the index.
+    pub expired: bool,
+    pub contents: String,
+    /// The line of the file `contents` starts on, counting from 1.
+    pub first_line: usize,
+    pub markdown: MarkdownConfig,
+}
+
+impl Page {
+    /// The page's `title`, from its front matter.
+    pub fn title(&self) -> &str {
+        &self.front_matter.title
+    }
+
+    fn type_config(&self) -> Option<TypeConfig> {
+        config().types.get(&self.front_matter.page_type).cloned()
+    }
+
+    fn template(&self) -> String {
+        if let Some(template) = self.type_config().and_then(|t| t.template.clone()) {
+            return template;
+        }
+        let template = format!("{}.html", self.front_matter.page_type);
+        match tera().get_template_names().any(|n| n == template) {
+            true => template,
+            false => "page.html".to_owned(),
+        }
+    }
+
+    fn lang(&self) -> String {
+        match &self.front_matter.lang {
+            Some(lang) => lang.clone(),
+            None => config().language.clone(),
+        }
+    }
+
+    fn author_ids(&self) -> impl Iterator<Item = &String> {
+        self.front_matter
+            .author
+            .iter()
+            .chain(&self.front_matter.authors)
+    }
+
+    /// Whether the page is listed in the index.
+    fn in_index(&self) -> bool {
+        let in_index = self.type_config().is_none_or(|t| t.in_index);
+        in_index && !self.standalone && !self.expired
+    }
+
+    /// Whether `tag` is in the page's `tags`.
+    fn has_tag(&self, tag: &str) -> bool {
+        match self.front_matter.extra.get("tags") {
+            Some(tera::Value::Array(tags)) => tags.iter().any(|t| t.as_str() == Some(tag)),
+            _ => false,
+        }
+    }
+
+    /// The directory the page is written to.
+    fn output_dir(&self) -> PathBuf {
+        WEBSITE_DIR.join(self.url.trim_matches('/'))
+    }
+
+    /// The files that redirect from the page's aliases.
+    fn alias_paths(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.front_matter.aliases.iter().map(|alias| {
+            let path = WEBSITE_DIR.join(alias.trim_matches('/'));
+            match path.extension() {
+                Some(_) => path,
+                None => path.join("index.html"),
+            }
+        })
+    }
+
+    fn summary(&self) -> PageSummary {
+        PageSummary {
+            title: self.front_matter.title.clone(),
+            date: self.front_matter.date.clone(),
+            slug: self.slug.clone(),
+            url: self.url.clone(),
+        }
+    }
+}
+
+/// Pinned pages first, then by `sort_by`, with weight breaking ties between
+/// dates and date breaking ties between weights. Undated pages go last, and
+/// so do unweighted ones.
+fn sort_pages(pages: &mut [Page], sort_by: SortBy) {
+    let weight = |page: &Page| page.front_matter.weight.unwrap_or(i64::MAX);
+    let newest_first = |a: &Page, b: &Page| b.date.cmp(&a.date);
+    let oldest_first = |a: &Page, b: &Page| match (a.date, b.date) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (a, b) => b.is_some().cmp(&a.is_some()),
+    };
+
+    pages.sort_by(|a, b| {
+        let pinned = b.front_matter.pinned.cmp(&a.front_matter.pinned);
+        pinned.then_with(|| match sort_by {
+            SortBy::Date => newest_first(a, b).then(weight(a).cmp(&weight(b))),
+            SortBy::DateAsc => oldest_first(a, b).then(weight(a).cmp(&weight(b))),
+            SortBy::Title => {
+                let title = |page: &Page| page.front_matter.title.to_lowercase();
+                title(a).cmp(&title(b))
+            }
+            SortBy::Weight => weight(a).cmp(&weight(b)).then(newest_first(a, b)),
+        })
+    });
+}
+
+/// What templates see of a page, in `page.html` and as `posts` in
+/// `index.html`.
+#[derive(Serialize)]
+struct PageContext {
+    title: String,
+    slug: String,
+    url: String,
+    /// As written in the front matter.
+    date: String,
+    /// As `2024-05-18`, if there is a date.
+    iso_date: Option<String>,
+    /// When the page's source was last committed, as RFC 3339, with
+    /// `git_dates` on.
+    updated: Option<String>,
+    #[serde(rename = "type")]
+    page_type: String,
+    pinned: bool,
+    contents: String,
+    /// The previous and next posts by date.
+    prev: Option<PageSummary>,
+    next: Option<PageSummary>,
+    series: Option<SeriesContext>,
+    authors: Vec<AuthorContext>,
+    lang: String,
+    /// Every version of this page, this one included.
+    translations: Vec<TranslationContext>,
+    /// The page's markdown, if it's published.
+    source_url: Option<String>,
+    /// Urls of the page's own stylesheets and scripts.
+    extra_css: Vec<String>,
+    extra_js: Vec<String>,
+    extra: HashMap<String, tera::Value>,
+}
+
+#[derive(Serialize, Clone)]
+struct TranslationContext {
+    lang: String,
+    title: String,
+    url: String,
+    /// Whether this is the page being rendered.
+    current: bool,
+}
+
+#[derive(Serialize, Clone)]
+struct AuthorContext {
+    id: String,
+    #[serde(flatten)]
+    author: AuthorConfig,
+    /// Only if there's an `author.html` template to make a page for them.
+    page_url: Option<String>,
+}
+
+impl AuthorContext {
+    /// Authors missing from the config are just named by their id.
+    fn new(id: &str, has_page: bool) -> AuthorContext {
+        let author = config().authors.get(id).cloned();
+        AuthorContext {
+            id: id.to_owned(),
+            author: author.unwrap_or_else(|| AuthorConfig {
+                name: id.to_owned(),
+                ..AuthorConfig::default()
+            }),
+            page_url: has_page.then(|| format!("/authors/{}/", slug::slugify(id))),
+        }
+    }
+}
+
+/// A series, with its parts in order.
+#[derive(Serialize, Clone)]
+struct SeriesContext {
+    name: String,
+    /// Only if there's a `series.html` template to make a page for it.
+    url: Option<String>,
+    parts: Vec<SeriesPart>,
+}
+
+#[derive(Serialize, Clone)]
+struct SeriesPart {
+    #[serde(flatten)]
+    page: PageSummary,
+    /// Whether this is the page being rendered.
+    current: bool,
+}
+
+/// The parts of a page that other pages can see, via `get_page()`.
+#[derive(Serialize, Clone)]
+pub struct PageSummary {
+    pub title: String,
+    pub date: String,
+    pub slug: String,
+    pub url: String,
+}
+
+/// What shortcodes know about the page they're on, as `page`.
+#[derive(Serialize)]
+struct ShortcodePageContext<'a> {
+    #[serde(flatten)]
+    summary: PageSummary,
+    iso_date: Option<String>,
+    #[serde(rename = "type")]
+    page_type: &'a str,
+    extra: &'a HashMap<String, tera::Value>,
+    /// The content directory the page is in, which its images are relative to.
+    root: &'a Path,
+}
+
+/// What templates see as `build`: when and what the site was built from.
+#[derive(Serialize)]
+struct BuildContext {
+    /// As RFC 3339.
+    timestamp: String,
+    /// The short hash of the site's `HEAD`, if it's in a git repo.
+    git_commit: Option<String>,
+    generator: &'static str,
+    version: &'static str,
+}
+
+impl BuildContext {
+    /// The metadata of a build starting now.
+    fn new() -> BuildContext {
+        BuildContext {
+            timestamp: Local::now().to_rfc3339_opts(SecondsFormat::Secs, false),
+            git_commit: git::head_commit(),
+            generator: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+        }
+    }
+}
+
+/// Makes a template context out of `context`, plus the variables every
+/// template gets (`site`, `data`, `feeds`, `build`, and `favicons`).
+fn template_context<T: Serialize>(context: &T) -> Result<tera::Context> {
+    let mut context = tera::Context::from_serialize(context)?;
+    let site = site();
+    context.insert("site", &*site.config);
+    context.insert("data", site.data.get().expect("data is loaded at startup"));
+    context.insert("feeds", site.feeds.get().map_or(&[][..], |feeds| feeds));
+    context.insert("build", &site.build);
+    if let Some(favicons) = site.favicons.get() {
+        context.insert("favicons", favicons);
+    }
+    Ok(context)
+}
+
+/// Renders template `name` with `context`, and the usual site-wide values.
+fn render_template<T: Serialize>(name: &str, context: &T) -> Result<String> {
+    let context = template_context(context)?;
+    timings::time(Phase::Templates, || Ok(tera().render(name, &context)?))
+}
+
+/// Turns markdown into html, with diagrams and math rendered, code blocks
+/// highlighted, and punctuation smartened if it's turned on. Diagrams and
+/// math that can't be rendered are left as they are, and added to `errors`.
+fn render_markdown(
+    contents: &str,
+    options: &MarkdownConfig,
+    errors: &mut Vec<anyhow::Error>,
+) -> String {
+    let html = timings::time(Phase::Markdown, || markdown_to_html(contents, options));
+    let document = timings::time(Phase::Postprocessing, || {
+        if config().validate_html {
+            errors.extend(
+                html::parse_errors(&html)
+                    .into_iter()
+                    .map(anyhow::Error::msg),
+            );
+        }
+        let document = kuchikiki::parse_html().one(html);
+
+        errors.extend(html::render_diagrams(&document, &config().diagrams));
+        errors.extend(html::render_math(&document));
+        document
+    });
+    timings::time(Phase::Highlighting, || {
+        html::syntax_highlight_code_blocks(&document)
+    });
+
+    timings::time(Phase::Postprocessing, || {
+        if options.smart_punctuation {
+            html::smarten_punctuation(&document);
+        }
+        html::get_body_children_of_document(&document)
+            .map(|nr| nr.to_string())
+            .collect()
+    })
+}
+
+/// Stages copying a page's images from `content_dir` into `page_dir`, and
+/// points `@/` links at the pages they're to, using `urls` (by path under
+/// their content directory).
+fn process_html<P: AsRef<Path>>(
+    html: &str,
+    content_dir: &Path,
+    page_dir: P,
+    staging: &mut Staging,
+    urls: &HashMap<String, String>,
+) -> Result<String> {
+    let document = kuchikiki::parse_html().one(html);
+
+    for (from, to) in html::find_media_and_add_dimensions(&document, content_dir, page_dir) {
+        staging.copy(from, to);
+    }
+    html::resolve_internal_links(&document, urls)?;
+
+    Ok(html::get_body_children_of_document(&document)
+        .map(|nr| nr.to_string())
+        .collect())
+}
+
+/// Copies a page's own stylesheets or scripts from `content/` next to it,
+/// named with a hash of their contents so they can be cached forever.
+/// Returns their full urls. Ones that aren't in `content/` are left where
+/// they are.
+fn page_assets(paths: &[String], page: &Page, staging: &mut Staging) -> Result<Vec<String>> {
+    let mut urls = Vec::new();
+    for path in paths {
+        if path.contains("://") || path.starts_with("//") {
+            urls.push(path.clone());
+            continue;
+        }
+        if path.starts_with('/') {
+            urls.push(config().root_relative_url(path));
+            continue;
+        }
+        let from = page.root.join(path);
+        let contents =
+            fs::read(&from).with_context(|| format!("cannot read {}", from.to_string_lossy()))?;
+        let hash = checksum::checksum(&contents);
+        let name = Path::new(path);
+        let fingerprinted = match (name.file_stem(), name.extension()) {
+            (Some(stem), Some(extension)) => format!(
+                "{}.{}.{}",
+                stem.to_string_lossy(),
+                &hash[..8],
+                extension.to_string_lossy()
+            ),
+            _ => format!("{path}.{}", &hash[..8]),
+        };
+        let to = name.with_file_name(fingerprinted);
+        staging.copy(&from, page.output_dir().join(&to));
+        urls.push(config().root_relative_url(&format!("{}{}", page.url, to.to_string_lossy())));
+    }
+    Ok(urls)
+}
+
+/// Touches up a fully rendered page.
+fn postprocess_page(html: &str, page: &PageContext) -> String {
+    let has_translations = page.translations.len() >= 2;
+    // parsing and writing out a page isn't free, or quite a no-op
+    let config = config();
+    let is_touched = config.microformats
+        || has_translations
+        || !config.prevent_widows.is_empty()
+        || config.external_links.is_on();
+    if !is_touched {
+        return html.to_owned();
+    }
+
+    let document = kuchikiki::parse_html().one(html);
+
+    if config.microformats {
+        microformats::add_microformats(&document, page);
+    }
+    if has_translations {
+        let alternates: Vec<_> = page
+            .translations
+            .iter()
+            .map(|t| (t.lang.clone(), config.absolute_url(&t.url)))
+            .collect();
+        html::add_hreflang_links(&document, &alternates);
+    }
+    html::prevent_widows(&document, &config.prevent_widows);
+    html::mark_external_links(&document, &config.external_links);
+
+    document.to_string()
+}
+
+/// Gives `html`, the page at `url`, a content security policy for what's in
+/// it: in a `<meta>` tag, or in `headers`, for the `_headers` file. Pages are
+/// finished first, so the policy covers everything they end up with.
+fn add_csp(
+    html: String,
+    url: &str,
+    headers: &mut BTreeMap<String, BTreeMap<String, String>>,
+) -> String {
+    let csp = config().csp;
+    if csp == CspMode::Off {
+        return html;
+    }
+    let document = kuchikiki::parse_html().one(html.as_str());
+    let policy = csp::policy(&document);
+    match csp {
+        CspMode::Meta => {
+            csp::add_meta(&document, &policy);
+            document.to_string()
+        }
+        CspMode::Headers => {
+            headers
+                .entry(url.to_owned())
+                .or_default()
+                .insert("Content-Security-Policy".to_owned(), policy);
+            html
+        }
+        CspMode::Off => html,
+    }
+}
+
+/// Reads dates like `2024-05-18`, `2024/05/18`, `May 18, 2024`, or
+/// `18 May 2024`, ignoring any time that comes after.
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    let date = date.trim();
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(date) {
+        return Some(datetime.date_naive());
+    }
+    ["%Y-%m-%d", "%Y/%m/%d", "%B %d, %Y", "%d %B %Y"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(date, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(date.get(..10)?, "%Y-%m-%d").ok())
+}
+
+/// Works out a page's date from its front matter, falling back on its
+/// filename. Also returns what was wrong with the date, if anything.
+fn read_page_date(date: &str, path: &Path) -> (Option<NaiveDate>, Option<String>) {
+    let file_date = get_date_from_path(path);
+    if date.is_empty() {
+        return (file_date, None);
+    }
+
+    let parsed = parse_date(date).or_else(|| {
+        // dates like `5/18` take their year from the filename
+        let year = file_date?.year();
+        NaiveDate::parse_from_str(&format!("{year}/{date}"), "%Y/%m/%d").ok()
+    });
+
+    match (parsed, file_date) {
+        (None, _) => (file_date, Some(format!("cannot read date `{date}`"))),
+        (Some(parsed), Some(file_date)) if parsed != file_date => (
+            Some(parsed),
+            Some(format!("date `{date}` doesn't match the filename")),
+        ),
+        (parsed, _) => (parsed, None),
+    }
+}
+
+/// The site's markdown options, with a page's `changes` on top.
+fn markdown_options(changes: &FrontMatterValues) -> Result<MarkdownConfig> {
+    if changes.is_empty() {
+        return Ok(config().markdown.clone());
+    }
+    let tera::Value::Object(mut options) = serde_json::to_value(&config().markdown)? else {
+        unreachable!("options are a struct");
+    };
+    options.extend(changes.clone());
+    Ok(serde_json::from_value(tera::Value::Object(options))?)
+}
+
+/// Reports a problem with a page's front matter as `path:line: problem`.
+/// It's only a warning, unless front matter is `strict`.
+fn front_matter_problem(path: &Path, line: Option<usize>, problem: &str) -> Result<()> {
+    let location = match line {
+        Some(line) => format!("{}:{line}", path.to_string_lossy()),
+        None => path.to_string_lossy().into_owned(),
+    };
+    if config().front_matter.strict {
+        bail!("{location}: {problem}");
+    }
+    warn!("{location}: {problem}");
+    Ok(())
+}
+
+/// Whether `path`, joined onto a directory, is something inside it: it's
+/// relative, names something, and doesn't go up with `..`.
+fn stays_inside(path: &Path) -> bool {
+    path.components().any(|c| matches!(c, Component::Normal(_)))
+        && path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Reads the date from a filename like `20240518_what-i-ate.md`.
+fn get_date_from_path<P: AsRef<Path>>(path: P) -> Option<NaiveDate> {
+    let stem = path.as_ref().file_stem()?.to_str()?;
+    let (date, _) = stem.split_once('_')?;
+    NaiveDate::parse_from_str(date, "%Y%m%d").ok()
+}
+
+/// `20240518_what-i-ate.md` and `what-i-ate.md` both become `what-i-ate`.
+fn get_slug_from_path<P: AsRef<Path>>(path: P) -> String {
+    let stem = path
+        .as_ref()
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    match stem.split_once('_') {
+        Some((prefix, slug)) if prefix.chars().all(|c| c.is_ascii_digit()) => slug.to_owned(),
+        _ => stem.to_owned(),
+    }
+}
+
+type FrontMatterValues = serde_json::Map<String, tera::Value>;
+
+/// Everything in `dir`, a content directory or one in it, going through
+/// symlinked directories if `follow_symlinks` is on. A symlink back to a
+/// directory it's in is skipped, rather than followed forever.
+fn walk_content<P: AsRef<Path>>(dir: P) -> impl Iterator<Item = walkdir::Result<DirEntry>> {
+    WalkDir::new(dir)
+        .follow_links(config().follow_symlinks)
+        .sort_by_file_name()
+        .into_iter()
+        // content directories can be repos of their own
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .filter(|entry| match entry {
+            Err(err) if err.loop_ancestor().is_some() => {
+                let path = err.path().unwrap_or(Path::new("")).to_owned();
+                let site = site();
+                let mut loops = site.loops.lock().unwrap();
+                if loops.insert(path.clone()) {
+                    let path = path.to_string_lossy();
+                    warn!("skipping {path}, a symlink to a directory it's in");
+                }
+                false
+            }
+            _ => true,
+        })
+}
+
+/// The `defaults` in each `_index.md`'s front matter, by directory.
+fn load_section_defaults() -> Result<HashMap<PathBuf, FrontMatterValues>> {
+    let mut sections = HashMap::new();
+
+    let config = config();
+    let entries = config
+        .content_dirs
+        .iter()
+        .flat_map(|dir| walk_content(dir.path()).filter_map(|e| e.ok()));
+    for entry in entries {
+        let path = entry.path();
+        if !path.is_file() || !path.ends_with("_index.md") {
+            continue;
+        }
+
+        let yaml_matter = Matter::<YAML>::new();
+        let result = yaml_matter.parse(&fs::read_to_string(path)?);
+        let Some(tera::Value::Object(mut front_matter)) =
+            result.data.map(|d| d.deserialize()).transpose()?
+        else {
+            continue;
+        };
+        if let Some(tera::Value::Object(defaults)) = front_matter.remove("defaults") {
+            let dir = path.parent().unwrap_or(path).to_owned();
+            sections.insert(dir, defaults);
+        }
+    }
+
+    Ok(sections)
+}
+
+/// The front matter a page starts with before its own is added: the config's
+/// `defaults`, then those of each `_index.md` above it, outermost first.
+fn front_matter_defaults(
+    path: &Path,
+    sections: &HashMap<PathBuf, FrontMatterValues>,
+) -> FrontMatterValues {
+    let mut defaults = config().defaults.clone();
+    let dirs: Vec<_> = path.ancestors().skip(1).collect();
+    for dir in dirs.into_iter().rev() {
+        if let Some(section) = sections.get(dir) {
+            defaults.extend(section.clone());
+        }
+    }
+    defaults
+}
+
+/// Removes every file that builds have written to the output directory
+/// (leaving anything that was put there by hand), then the cache.
+pub fn clean() -> Result<()> {
+    let _lock = lock::Lock::acquire(&*LOCK_PATH)?;
+    let mut state = State::load(&*STATE_PATH)?;
+
+    remove_outputs(&mut state, &WEBSITE_DIR)?;
+    remove_outputs(&mut state, &CAPSULE_DIR)?;
+    remove_outputs(&mut state, &EMAIL_DIR)?;
+
+    if CACHE_DIR.is_dir() {
+        fs::remove_dir_all(&*CACHE_DIR)?;
+    }
+
+    Ok(())
+}
+
+/// Deletes the files builds have written inside `dir` (or `dir` itself, if
+/// it's a file), and any directories that leaves empty.
+fn remove_outputs(state: &mut State, dir: &Path) -> Result<()> {
+    let (removed, kept) = std::mem::take(&mut state.outputs)
+        .into_iter()
+        .partition(|path| path.starts_with(dir));
+    state.outputs = kept;
+
+    for path in removed {
+        remove_output(&path)?;
+    }
+
+    Ok(())
+}
+
+/// Deletes a file a build wrote, and any directories that leaves empty.
+fn remove_output(path: &Path) -> Result<()> {
+    if path.is_file() {
+        fs::remove_file(path)?;
+        info!("Removing {}", path.as_os_str().to_string_lossy());
+    }
+    // clear out page directories that are now empty
+    for dir in path.ancestors().skip(1) {
+        if dir == *WEBSITE_DIR || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Files in `content/` that no page uses: not copied for any page, and not
+/// mentioned in any markdown, e.g. by a draft.
+fn find_orphaned_files(staging: &Staging) -> Result<Vec<PathBuf>> {
+    let used: HashSet<&Path> = staging.copy_sources().collect();
+
+    let mut markdown = String::new();
+    for entry in config()
+        .content_dirs
+        .iter()
+        .flat_map(|dir| walk_content(dir.path()))
+    {
+        let path = entry?.into_path();
+        if path.is_file() && path.extension().is_some_and(|e| e == "md") {
+            markdown.push_str(&fs::read_to_string(&path)?);
+        }
+    }
+
+    // symlinks aren't followed here: what they point to can be used by
+    // something else entirely
+    let config = config();
+    let mut files = Vec::new();
+    for dir in &config.content_dirs {
+        let entries = WalkDir::new(dir.path())
+            .into_iter()
+            .filter_entry(|entry| entry.file_name() != ".git");
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type().is_file()
+                && !path.starts_with(&*WELL_KNOWN_DIR)
+                && path.extension().is_none_or(|e| e != "md")
+            {
+                files.push((dir.path(), entry.into_path()));
+            }
+        }
+    }
+
+    Ok(files
+        .into_iter()
+        .filter(|(root, path)| {
+            let name = path.strip_prefix(root).unwrap_or(path);
+            !used.contains(path.as_path()) && !markdown.contains(&*name.to_string_lossy())
+        })
+        .map(|(_, path)| path)
+        .collect())
+}
+
+/// Asks `question` on the terminal, for a yes or no. Without a terminal to
+/// ask on, it's an error.
+fn confirm(question: &str) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        bail!("not asking whether to delete files without a terminal; pass `--yes`");
+    }
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Runs `args.command`, as `blog` does. Logging is left to the caller; see
+/// [`logging::init`].
+pub fn run(args: &Args) -> Result<()> {
+    match &args.command {
+        Command::Build => build(args),
+        Command::Clean => clean(),
+        Command::Doctor => doctor::run(),
+        Command::Init(dir) => init::run(dir),
+        Command::Export(format) => match format.as_str() {
+            "epub" => build(args),
+            _ => bail!("can't export to `{format}`, only epub"),
+        },
+        Command::Deploy(target) => {
+            if target != "s3" {
+                bail!("can't deploy to `{target}`, only s3");
+            }
+            build(args)?;
+            deploy::s3()
+        }
+        Command::Import(generator, dir) => {
+            load_config(args)?;
+            import::run(generator, dir)
+        }
+        Command::New(title) => {
+            load_config(args)?;
+            let page_type = args.page_type.clone().unwrap_or_else(default_page_type);
+            archetypes::new_post(title, &page_type)
+        }
+        Command::Stats => {
+            stats::print(&read_pages(args)?);
+            Ok(())
+        }
+        Command::CheckLinks => {
+            load_config(args)?;
+            links::check_external_links(&CACHE_DIR.join("links.json"))
+        }
+    }
+}
+
+/// Pages in `content/`, as read by [`read_pages`].
+pub struct Content {
+    /// Pages to build, in no particular order.
+    pub pages: Vec<Page>,
+    /// Paths of drafts, which aren't built.
+    pub drafts: Vec<PathBuf>,
+    /// Output directories and alias redirects of expired pages, which should
+    /// be removed.
+    pub expired_outputs: Vec<PathBuf>,
+}
+
+/// Reads every page in `content/`, leaving out drafts, and posts scheduled
+/// for later unless `args.future`.
+pub fn read_pages(args: &Args) -> Result<Content> {
+    load_config(args)?;
+    read_content(args)
+}
+
+/// Reads the pages of the site that's loaded, as [`read_pages`] does.
+fn read_content(args: &Args) -> Result<Content> {
+    let mut pages = Vec::new();
+    let mut drafts = Vec::new();
+    let mut expired_outputs = Vec::new();
+    // where each page so far came from, by slug and by output directory
+    let mut slugs: HashMap<String, PathBuf> = HashMap::new();
+    let mut output_dirs = HashMap::new();
+
+    let section_defaults = load_section_defaults()?;
+
+    for (content_dir, entry) in config().content_dirs.iter().flat_map(|dir| {
+        walk_content(dir.path())
+            .filter_map(|e| e.ok())
+            .map(move |entry| (dir, entry))
+    }) {
+        let root = content_dir.path();
+        let path = entry.into_path();
+        if path.is_file()
+            && path.extension().is_some_and(|s| s == "md")
+            && !path.ends_with("_index.md")
+            && !path.starts_with(&*WELL_KNOWN_DIR)
+        {
+            info!("Reading {}", path.as_os_str().to_string_lossy());
+
+            let file_contents = fs::read_to_string(&path)?;
+
+            let yaml_matter = Matter::<YAML>::new();
+            let result = yaml_matter.parse(&file_contents);
+
+            let mut front_matter = front_matter_defaults(&path, &section_defaults);
+            if let Some(tera::Value::Object(values)) =
+                result.data.map(|d| d.deserialize()).transpose()?
+            {
+                front_matter.extend(values);
+            }
+            let values = front_matter.clone();
+            let front_matter: FrontMatter =
+                serde_json::from_value(tera::Value::Object(front_matter))
+                    .with_context(|| format!("bad front matter in {}", path.to_string_lossy()))?;
+            let contents = result.content;
+            let markdown = markdown_options(&front_matter.markdown)
+                .with_context(|| format!("bad markdown options in {}", path.to_string_lossy()))?;
+            let first_line = file_contents
+                .rfind(&contents)
+                .map_or(1, |start| file_contents[..start].lines().count() + 1);
+
+            let problems = schema::check(&values, &front_matter.extra, &config().front_matter);
+            for (key, problem) in problems {
+                let line = schema::key_line(&file_contents, &key);
+                front_matter_problem(&path, line, &problem)?;
+            }
+
+            if front_matter.draft {
+                debug!("{} is a draft, skipping", path.to_string_lossy());
+                drafts.push(path);
+                continue;
+            }
+
+            let (date, problem) = read_page_date(&front_matter.date, &path);
+            if let Some(problem) = problem {
+                let line = schema::key_line(&file_contents, "date");
+                front_matter_problem(&path, line, &problem)?;
+            }
+            if let Some(date) = date {
+                if !args.future && date > Local::now().date_naive() {
+                    debug!(
+                        "{} is scheduled for {date}, skipping",
+                        path.to_string_lossy()
+                    );
+                    continue;
+                }
+            }
+
+            // aliases are written as files, so they can't point outside the site
+            if let Some(alias) = front_matter
+                .aliases
+                .iter()
+                .find(|alias| !stays_inside(Path::new(alias.trim_matches('/'))))
+            {
+                let location = match schema::key_line(&file_contents, "aliases") {
+                    Some(line) => format!("{}:{line}", path.to_string_lossy()),
+                    None => path.to_string_lossy().into_owned(),
+                };
+                bail!("{location}: alias `{alias}` isn't a path inside the site");
+            }
+
+            let slug = front_matter
+                .slug
+                .clone()
+                .unwrap_or_else(|| get_slug_from_path(&path));
+            let standalone = date.is_none() || path.starts_with(root.join("pages"));
+            let url = content_dir.prefixed(&match standalone {
+                true => format!("/{slug}/"),
+                false => config().permalink(&slug, date)?,
+            });
+
+            let expired = front_matter
+                .expires
+                .as_deref()
+                .and_then(parse_date)
+                .is_some_and(|expires| expires <= Local::now().date_naive());
+
+            let page = Page {
+                path: path.clone(),
+                root: root.to_owned(),
+                front_matter,
+                slug,
+                url,
+                date,
+                standalone,
+                expired,
+                contents,
+                first_line,
+                markdown,
+            };
+
+            if expired && config().delete_expired {
+                info!("{} has expired, removing", path.to_string_lossy());
+                expired_outputs.push(page.output_dir());
+                expired_outputs.extend(page.alias_paths());
+                continue;
+            }
+            // the first page with a slug or output directory keeps it
+            let other = slugs
+                .get(&page.slug)
+                .or_else(|| output_dirs.get(&page.output_dir()));
+            if let Some(other) = other {
+                let problem = format!(
+                    "{} has the same slug or url as {}",
+                    path.to_string_lossy(),
+                    other.to_string_lossy()
+                );
+                if args.strict || config().strict {
+                    bail!(problem);
+                }
+                warn!("{problem}, skipping it");
+                continue;
+            }
+            slugs.insert(page.slug.clone(), path.clone());
+            output_dirs.insert(page.output_dir(), path);
+            pages.push(page);
+        }
+    }
+
+    Ok(Content {
+        pages,
+        drafts,
+        expired_outputs,
+    })
+}
+
+/// Builds the site into `website/`, as `blog build` does.
+pub fn build(args: &Args) -> Result<()> {
+    let _lock = lock::Lock::acquire(&*LOCK_PATH)?;
+    // only this build's warnings count towards strict mode
+    let earlier_warnings = logging::warnings();
+    timings::reset();
+    let mut state = timings::time(Phase::State, || State::load(&*STATE_PATH))?;
+    load_config(args)?;
+    if let Some(command) = &config().hooks.pre_build {
+        hooks::run("pre_build", command, &[])?;
+    }
+    let _ = site().data.set(data::load_data_dir(&*DATA_DIR)?);
+
+    let Content {
+        mut pages,
+        expired_outputs,
+        ..
+    } = read_content(args)?;
+
+    sort_pages(&mut pages, config().sort_by);
+
+    // every page needs to be known before rendering, so that templates can
+    // look up other pages with `get_page()`
+    let _ = site().pages.set(
+        pages
+            .iter()
+            .map(|page| (page.slug.clone(), page.summary()))
+            .collect(),
+    );
+
+    if config().tag_feeds {
+        let mut tags = BTreeSet::new();
+        for page in pages.iter().filter(|page| page.in_index()) {
+            if let Some(tera::Value::Array(page_tags)) = page.front_matter.extra.get("tags") {
+                tags.extend(page_tags.iter().filter_map(|tag| tag.as_str()));
+            }
+        }
+        let _ = site()
+            .feeds
+            .set(tags.into_iter().map(feeds::Feed::new).collect());
+    }
+
+    // an alias can't stand in for a real page
+    let page_paths: HashSet<PathBuf> = pages
+        .iter()
+        .map(|page| page.output_dir().join("index.html"))
+        .collect();
+    for page in &pages {
+        if let Some(path) = page.alias_paths().find(|path| page_paths.contains(path)) {
+            bail!(
+                "an alias of `{}` would overwrite {}",
+                page.slug,
+                path.to_string_lossy()
+            );
+        }
+    }
+
+    let mut posts = Vec::new();
+    // which file each page came from, by where it's written to
+    let mut sources = HashMap::new();
+    let mut staging = Staging::default();
+
+    if config().redirects_file {
+        let redirects = pages.iter().flat_map(|page| {
+            let aliases = page.front_matter.aliases.iter();
+            aliases.map(|alias| (alias.as_str(), page.url.as_str()))
+        });
+        staging.write(
+            WEBSITE_DIR.join("_redirects"),
+            hosting::redirects_file(redirects),
+        );
+    }
+    let favicons = match &config().favicon {
+        Some(options) => favicons::generate(options, &mut staging)?,
+        None => favicons::Favicons::default(),
+    };
+    let _ = site().favicons.set(favicons);
+
+    if let Some(cname) = &config().cname {
+        staging.write(WEBSITE_DIR.join("CNAME"), format!("{}\n", cname.trim()));
+    }
+    if config().nojekyll {
+        staging.write(WEBSITE_DIR.join(".nojekyll"), String::new());
+    }
+    // `_headers` is written after everything's rendered, since it can have
+    // each page's policy in it
+    let mut headers = config().headers.clone();
+
+    // chronological neighbours, for prev/next links
+    let mut neighbours = HashMap::new();
+    let mut chronological: Vec<&Page> = pages.iter().filter(|page| page.in_index()).collect();
+    chronological.sort_by_key(|page| page.date);
+    for (i, page) in chronological.iter().enumerate() {
+        let prev = i.checked_sub(1).map(|i| chronological[i].summary());
+        let next = chronological.get(i + 1).map(|page| page.summary());
+        neighbours.insert(page.slug.clone(), (prev, next));
+    }
+
+    // series, with their parts oldest first
+    let has_series_template = tera().get_template_names().any(|n| n == "series.html");
+    let mut series: HashMap<String, SeriesContext> = HashMap::new();
+    for page in &chronological {
+        let Some(name) = &page.front_matter.series else {
+            continue;
+        };
+        let series = series.entry(name.clone()).or_insert_with(|| SeriesContext {
+            name: name.clone(),
+            url: has_series_template.then(|| format!("/series/{}/", slug::slugify(name))),
+            parts: Vec::new(),
+        });
+        series.parts.push(SeriesPart {
+            page: page.summary(),
+            current: false,
+        });
+    }
+
+    // pages whose bodies changed since the last build, for hooks
+    let mut changed = Vec::new();
+    // pages whose bodies changed since webmentions were last sent for them
+    let mut to_mention = Vec::new();
+    let mut checksums = BTreeMap::new();
+
+    let git_dates = match config().git_dates {
+        true => git::last_modified()?,
+        false => HashMap::new(),
+    };
+
+    let has_email_template = tera().get_template_names().any(|n| n == "email.html");
+
+    // each author's posts, in index order
+    let has_author_template = tera().get_template_names().any(|n| n == "author.html");
+    let mut author_posts: HashMap<String, Vec<PageSummary>> = HashMap::new();
+    for page in pages.iter().filter(|page| page.in_index()) {
+        for id in page.author_ids() {
+            let posts = author_posts.entry(id.clone()).or_default();
+            posts.push(page.summary());
+        }
+    }
+
+    // translations, by translation key
+    let mut translations: HashMap<String, Vec<TranslationContext>> = HashMap::new();
+    for page in &pages {
+        let Some(key) = &page.front_matter.translation_key else {
+            continue;
+        };
+        translations
+            .entry(key.clone())
+            .or_default()
+            .push(TranslationContext {
+                lang: page.lang(),
+                title: page.front_matter.title.clone(),
+                url: page.url.clone(),
+                current: false,
+            });
+    }
+
+    // for `@/` links
+    let urls_by_path: HashMap<String, String> = pages
+        .iter()
+        .filter_map(|page| {
+            let path = page.path.strip_prefix(&page.root).ok()?;
+            let path = path.to_string_lossy().replace('\\', "/");
+            Some((path, page.url.clone()))
+        })
+        .collect();
+
+    let mut render_cache = RenderCache::new(CACHE_DIR.join("render"), args.force);
+    // finished once the stylesheets they inline are staged
+    let mut emails = Vec::new();
+    // every page's metadata, for `pages.json`
+    let mut pages_json = Vec::new();
+    // link lines for the capsule's index, in index order
+    let mut capsule_index = Vec::new();
+    // exports skip writing the site
+    let mut book = match args.command {
+        Command::Export(_) => {
+            let mut title = config().title.clone();
+            if let Some(year) = args.year {
+                title.push_str(&format!(" ({year})"));
+            }
+            if let Some(tag) = &args.tag {
+                title.push_str(&format!(": {tag}"));
+            }
+            Some(epub::Book::new(title))
+        }
+        _ => None,
+    };
+
+    // the ones registered by whatever's using the library, then the site's
+    let registered = plugins::PLUGINS.lock().unwrap();
+    let wasm_plugins = plugins::wasm::load_dir(&PLUGIN_DIR)?;
+    let plugins: Vec<&dyn plugins::Plugin> = registered
+        .iter()
+        .map(|plugin| plugin.as_ref() as &dyn plugins::Plugin)
+        .chain(
+            wasm_plugins
+                .iter()
+                .map(|plugin| plugin as &dyn plugins::Plugin),
+        )
+        .collect();
+    let post_summaries: Vec<PageSummary> = pages
+        .iter()
+        .filter(|page| page.in_index())
+        .map(Page::summary)
+        .collect();
+
+    for page in pages {
+        timings::start_page(&page.path);
+        staging.set_page(Some(&page.slug));
+        let mut shortcode_context = template_context(&HashMap::<&str, tera::Value>::new())?;
+        shortcode_context.insert(
+            "page",
+            &ShortcodePageContext {
+                summary: page.summary(),
+                iso_date: page.date.map(|date| date.to_string()),
+                page_type: &page.front_matter.page_type,
+                extra: &page.front_matter.extra,
+                root: &page.root,
+            },
+        );
+        let mut contents = page.contents.clone();
+        for plugin in plugins.iter() {
+            contents = plugin
+                .transform_markdown(&page, contents)
+                .with_context(|| format!("a plugin failed on {}", page.path.to_string_lossy()))?;
+        }
+        let rendered = timings::time(Phase::Templates, || {
+            shortcodes::render_shortcodes(&contents, &tera(), &shortcode_context)
+        })
+        .map_err(|err| err.in_file(&page.path, page.first_line))?;
+        let mut errors = Vec::new();
+        let html_contents =
+            render_cache.get_or_render(&rendered.markdown, &page.markdown, |markdown| {
+                Ok(render_markdown(markdown, &page.markdown, &mut errors))
+            })?;
+        if !errors.is_empty() {
+            for err in errors {
+                warn!("{}: {err:#}", page.path.to_string_lossy());
+            }
+            render_cache.forget(&rendered.markdown, &page.markdown)?;
+        }
+        let html_contents = rendered.insert_html(&html_contents);
+
+        if config().gemini {
+            let gemtext = gemini::to_gemtext(&rendered.markdown, &page.markdown, &urls_by_path);
+            let capsule_dir = CAPSULE_DIR.join(page.url.trim_matches('/'));
+            let mut text = format!("# {}\n\n", page.front_matter.title);
+            if let Some(date) = page.date {
+                text.push_str(&format!("{date}\n\n"));
+            }
+            text.push_str(&gemtext.text);
+            text.push_str("\n\n=> / Go back\n");
+            staging.write(capsule_dir.join("index.gmi"), text);
+            for image in gemtext.images {
+                staging.copy(page.root.join(&image), capsule_dir.join(&image));
+            }
+            if page.in_index() {
+                let date = page.date.map(|date| format!("{date} ")).unwrap_or_default();
+                capsule_index.push(format!("=> {} {date}{}", page.url, page.front_matter.title));
+            }
+        }
+        if config().validate_html {
+            for problem in rendered
+                .html
+                .iter()
+                .flat_map(|html| html::parse_errors(html))
+            {
+                warn!("{}: {problem}", page.path.to_string_lossy());
+            }
+        }
+
+        let page_dir = page.output_dir();
+        let (prev, next) = neighbours.remove(&page.slug).unwrap_or_default();
+        let page_series = page.front_matter.series.as_deref().and_then(|name| {
+            let mut series = series.get(name)?.clone();
+            for part in &mut series.parts {
+                part.current = part.page.slug == page.slug;
+            }
+            Some(series)
+        });
+
+        let mut page_translations = page
+            .front_matter
+            .translation_key
+            .as_ref()
+            .and_then(|key| translations.get(key).cloned())
+            .unwrap_or_default();
+        for translation in &mut page_translations {
+            translation.current = translation.url == page.url;
+        }
+
+        // copies images to each page's directory
+        let html_contents = timings::time(Phase::Postprocessing, || {
+            process_html(
+                &html_contents,
+                &page.root,
+                &page_dir,
+                &mut staging,
+                &urls_by_path,
+            )
+        })
+        .with_context(|| format!("bad link in {}", page.path.to_string_lossy()))?;
+
+        let checksum = checksum::checksum(&html_contents);
+        if state.checksums.get(&page.url) != Some(&checksum) {
+            changed.push((page.slug.clone(), page.url.clone()));
+        }
+        if args.send_webmentions && state.mentioned.get(&page.url) != Some(&checksum) {
+            to_mention.push((page.url.clone(), checksum.clone(), html_contents.clone()));
+        }
+        checksums.insert(page.url.clone(), checksum);
+
+        if let Some(book) = &mut book {
+            let in_year = args
+                .year
+                .is_none_or(|year| page.date.is_some_and(|date| date.year() == year));
+            if page.in_index() && in_year && args.tag.as_ref().is_none_or(|tag| page.has_tag(tag)) {
+                book.add_chapter(
+                    &page_dir,
+                    &page.slug,
+                    &page.front_matter.title,
+                    page.date,
+                    &html_contents,
+                );
+            }
+        }
+
+        if config().text_output {
+            let document = kuchikiki::parse_html().one(html_contents.as_str());
+            let title = &page.front_matter.title;
+            let mut text = format!("{title}\n{}\n\n", "=".repeat(title.chars().count()));
+            if let Some(date) = page.date {
+                text.push_str(&format!("{date}\n\n"));
+            }
+            text.push_str(&plain_text::to_plain_text(&document));
+            staging.write(page_dir.join("index.txt"), text);
+        }
+
+        if config().accessibility.check {
+            let document = kuchikiki::parse_html().one(html_contents.as_str());
+            let problems = html::accessibility_problems(&document);
+            for problem in &problems {
+                warn!("{}: {problem}", page.path.to_string_lossy());
+            }
+            if config().accessibility.strict && !problems.is_empty() {
+                bail!("accessibility problems in {}", page.path.to_string_lossy());
+            }
+        }
+
+        let post_context = PageContext {
+            title: page.front_matter.title.clone(),
+            slug: page.slug.clone(),
+            url: page.url.clone(),
+            date: page.front_matter.date.clone(),
+            iso_date: page.date.map(|date| date.to_string()),
+            updated: git_dates
+                .get(&page.path)
+                .map(|date| date.to_utc().to_rfc3339_opts(SecondsFormat::Secs, true)),
+            page_type: page.front_matter.page_type.clone(),
+            pinned: page.front_matter.pinned,
+            contents: html_contents,
+            prev,
+            next,
+            series: page_series,
+            authors: page
+                .author_ids()
+                .map(|id| AuthorContext::new(id, has_author_template))
+                .collect(),
+            lang: page.lang(),
+            translations: page_translations,
+            source_url: config()
+                .source
+                .publish
+                .then(|| format!("{}index.md", page.url)),
+            extra_css: page_assets(&page.front_matter.extra_css, &page, &mut staging)?,
+            extra_js: page_assets(&page.front_matter.extra_js, &page, &mut staging)?,
+            extra: page.front_matter.extra.clone(),
+        };
+
+        let rendered = render_template(&page.template(), &post_context)?;
+        let rendered = timings::time(Phase::Postprocessing, || {
+            let rendered = postprocess_page(&rendered, &post_context);
+            if config().validate_html {
+                // the page's body was checked before it was tidied up by
+                // parsing it, but not its template
+                let mut problems = html::parse_errors(&rendered);
+                problems.extend(html::duplicate_ids(
+                    &kuchikiki::parse_html().one(rendered.as_str()),
+                ));
+                for problem in problems {
+                    warn!("{}: {problem}", page.path.to_string_lossy());
+                }
+            }
+            rendered
+        });
+
+        let mut rendered = rendered;
+        for plugin in plugins.iter() {
+            rendered = plugin
+                .transform_html(&page, rendered)
+                .with_context(|| format!("a plugin failed on {}", page.path.to_string_lossy()))?;
+        }
+
+        let rendered = add_csp(rendered, &page.url, &mut headers);
+        staging.write(page_dir.join("index.html"), rendered);
+        sources.insert(page_dir.join("index.html"), page.path.clone());
+
+        if config().source.publish {
+            let markdown = match config().source.front_matter {
+                true => fs::read_to_string(&page.path)
+                    .with_context(|| format!("cannot read {}", page.path.to_string_lossy()))?,
+                false => page.contents.trim_start().to_owned(),
+            };
+            staging.write(page_dir.join("index.md"), markdown);
+        }
+
+        if has_email_template && page.in_index() {
+            let rendered = render_template("email.html", &post_context)?;
+            emails.push((page.slug.clone(), rendered, page.url.clone()));
+        }
+
+        if config().json_output {
+            let mut json = serde_json::to_value(&post_context)?;
+            staging.write(
+                page_dir.join("index.json"),
+                serde_json::to_string_pretty(&json)?,
+            );
+            if let Some(json) = json.as_object_mut() {
+                json.remove("contents");
+            }
+            pages_json.push(json);
+        }
+
+        for (alias, alias_path) in page.front_matter.aliases.iter().zip(page.alias_paths()) {
+            let redirect_context = HashMap::from([("url", &page.url)]);
+            let rendered = render_template("redirect.html", &redirect_context)?;
+            let rendered = add_csp(rendered, alias, &mut headers);
+            staging.write(alias_path, rendered);
+        }
+
+        if page.in_index() {
+            posts.push(post_context);
+        }
+        timings::end_page();
+    }
+    staging.set_page(None);
+
+    render_cache.prune()?;
+    if let Some(book) = book {
+        if book.is_empty() {
+            bail!("no posts to export");
+        }
+        let path = PathBuf::from(format!("{}.epub", slug::slugify(book.title())));
+        book.write(&path, &staging)?;
+        info!("Writing {}", path.to_string_lossy());
+        return Ok(());
+    }
+    if config().json_output {
+        staging.write(
+            WEBSITE_DIR.join("pages.json"),
+            serde_json::to_string_pretty(&pages_json)?,
+        );
+    }
+    with_previews(|previews| previews.save())?;
+
+    for plugin in plugins.iter() {
+        for (path, contents) in plugin.outputs(&post_summaries)? {
+            if !stays_inside(&path) {
+                bail!(
+                    "a plugin's output {} isn't inside the output directory",
+                    path.to_string_lossy()
+                );
+            }
+            staging.write(WEBSITE_DIR.join(path), contents);
+        }
+    }
+
+    if has_series_template {
+        for series in series.values() {
+            let Some(url) = &series.url else {
+                continue;
+            };
+            let series_context = HashMap::from([("series", series)]);
+            let rendered = render_template("series.html", &series_context)?;
+            let rendered = add_csp(rendered, url, &mut headers);
+            staging.write(
+                WEBSITE_DIR.join(url.trim_matches('/')).join("index.html"),
+                rendered,
+            );
+        }
+    }
+
+    if has_author_template {
+        for (id, posts) in &author_posts {
+            let author = AuthorContext::new(id, true);
+            let author_context = HashMap::from([
+                ("author", tera::to_value(&author)?),
+                ("posts", tera::to_value(posts)?),
+            ]);
+            let rendered = render_template("author.html", &author_context)?;
+            let url = author.page_url.unwrap_or_default();
+            let rendered = add_csp(rendered, &url, &mut headers);
+            staging.write(
+                WEBSITE_DIR.join(url.trim_matches('/')).join("index.html"),
+                rendered,
+            );
+        }
+    }
+
+    if let Some(data) = site().data.get().unwrap().get("blogroll") {
+        let blogroll = blogroll::Blogroll::from_data(data)?;
+        staging.write(
+            WEBSITE_DIR.join(blogroll::OPML_URL.trim_start_matches('/')),
+            blogroll.opml(),
+        );
+        if tera().get_template_names().any(|n| n == "blogroll.html") {
+            let blogroll_context = HashMap::from([
+                ("blogroll", tera::to_value(&blogroll)?),
+                ("opml_url", tera::to_value(blogroll::OPML_URL)?),
+            ]);
+            let rendered = render_template("blogroll.html", &blogroll_context)?;
+            let rendered = add_csp(rendered, blogroll::URL, &mut headers);
+            staging.write(
+                WEBSITE_DIR
+                    .join(blogroll::URL.trim_matches('/'))
+                    .join("index.html"),
+                rendered,
+            );
+        }
+    }
+
+    if let Some(options) = &config().activitypub {
+        let mut outbox: Vec<&PageContext> = posts.iter().collect();
+        outbox.sort_by(|a, b| b.iso_date.cmp(&a.iso_date));
+        for (path, json) in [
+            (activitypub::WEBFINGER_PATH, activitypub::webfinger(options)),
+            (activitypub::ACTOR_PATH, activitypub::actor(options)),
+            (activitypub::OUTBOX_PATH, activitypub::outbox(&outbox)),
+        ] {
+            staging.write(
+                WEBSITE_DIR.join(path.trim_start_matches('/')),
+                serde_json::to_string_pretty(&json)?,
+            );
+        }
+    }
+
+    // `.well-known/` files, from the config and then `content/.well-known/`
+    let mut well_known = Vec::new();
+    for (name, contents) in &config().well_known {
+        if !stays_inside(Path::new(name)) {
+            bail!("`[well_known]` name `{name}` isn't a path inside `.well-known/`");
+        }
+        well_known.push((PathBuf::from(name), Some(contents.clone())));
+    }
+    if WELL_KNOWN_DIR.is_dir() {
+        for entry in walk_content(&*WELL_KNOWN_DIR) {
+            let path = entry?.into_path();
+            if path.is_file() {
+                let name = path.strip_prefix(&*WELL_KNOWN_DIR)?.to_owned();
+                well_known.push((name, None));
+            }
+        }
+    }
+    for (name, contents) in well_known {
+        let path = WEBSITE_DIR.join(".well-known").join(&name);
+        if staging.will_write(&path) {
+            bail!("more than one `.well-known/{}`", name.to_string_lossy());
+        }
+        match contents {
+            Some(contents) => staging.write(path, contents.clone()),
+            None => staging.copy(WELL_KNOWN_DIR.join(name), path),
+        }
+    }
+
+    for feed in site().feeds.get().into_iter().flatten() {
+        let mut tagged: Vec<&PageContext> = posts
+            .iter()
+            .filter(|post| match post.extra.get("tags") {
+                Some(tera::Value::Array(tags)) => {
+                    tags.iter().any(|t| t.as_str() == Some(&feed.tag))
+                }
+                _ => false,
+            })
+            .collect();
+        tagged.sort_by(|a, b| b.iso_date.cmp(&a.iso_date));
+        staging.write(
+            WEBSITE_DIR.join(feed.url.trim_start_matches('/')),
+            feeds::atom(feed, &tagged),
+        );
+    }
+
+    let featured: Vec<_> = posts.iter().filter(|post| post.pinned).collect();
+    let index_context = HashMap::from([("posts", posts.iter().collect()), ("featured", featured)]);
+
+    let rendered = render_template("index.html", &index_context)?;
+    let rendered = add_csp(rendered, "/", &mut headers);
+
+    staging.write(WEBSITE_DIR.join("index.html"), rendered);
+
+    if config().gemini {
+        let index = format!("# {}\n\n{}\n", config().title, capsule_index.join("\n"));
+        staging.write(CAPSULE_DIR.join("index.gmi"), index);
+    }
+
+    if tera().get_template_names().any(|n| n == "404.html") {
+        let rendered = render_template("404.html", &HashMap::<&str, tera::Value>::new())?;
+        let rendered = add_csp(rendered, "/404.html", &mut headers);
+        staging.write(WEBSITE_DIR.join("404.html"), rendered);
+    }
+
+    // last, once every page has its policy
+    if !headers.is_empty() {
+        staging.write(
+            WEBSITE_DIR.join("_headers"),
+            hosting::headers_file(&headers),
+        );
+    }
+
+    if let Some(css) = highlighting::css(&config().highlighting)? {
+        staging.write(WEBSITE_DIR.join("syntax.css"), css);
+    }
+    let site = site();
+    if !site.themes.is_empty() {
+        themes::stage_files(&site.themes, &state, &mut staging)?;
+    }
+
+    for (slug, rendered, url) in emails {
+        staging.set_page(Some(&slug));
+        let email = email::to_email(&rendered, &url, &staging);
+        staging.write(EMAIL_DIR.join(format!("{slug}.html")), email);
+    }
+    staging.set_page(None);
+
+    // last, so that what's hashed is what's written
+    if config().subresource_integrity {
+        let pages: Vec<(PathBuf, String)> = staging
+            .html_files()
+            .filter(|(path, _)| path.starts_with(&*WEBSITE_DIR))
+            .map(|(path, html)| {
+                let document = kuchikiki::parse_html().one(html);
+                let dir = path.parent().unwrap_or(&WEBSITE_DIR);
+                html::add_integrity(&document, dir, &staging);
+                (path.to_owned(), document.to_string())
+            })
+            .collect();
+        for (path, html) in pages {
+            staging.write(path, html);
+        }
+    }
+
+    let broken_links = links::find_broken_links(&staging);
+    for (path, link) in &broken_links {
+        let path = sources.get(path).unwrap_or(path);
+        warn!("{}: broken link to `{link}`", path.to_string_lossy());
+    }
+    if config().strict_links && !broken_links.is_empty() {
+        bail!("{} broken links", broken_links.len());
+    }
+
+    let orphans = find_orphaned_files(&staging)?;
+    for path in &orphans {
+        warn!("no page uses {}", path.to_string_lossy());
+    }
+    if args.delete_orphans && !orphans.is_empty() {
+        let question = format!("Remove these {} files?", orphans.len());
+        if args.yes || confirm(&question)? {
+            for path in orphans {
+                info!("Removing {}", path.to_string_lossy());
+                fs::remove_file(path)?;
+            }
+        }
+    }
+
+    let warnings = logging::warnings() - earlier_warnings;
+    if (args.strict || config().strict) && warnings > 0 {
+        bail!("{warnings} warnings, and strict mode is on");
+    }
+
+    // nothing is written until everything has rendered
+    let page_files = staging.page_files().clone();
+    let written = timings::time(Phase::Copying, || staging.commit(&mut state.copied))?;
+    // what pages were built into before but weren't this time, e.g. images
+    // they don't use any more, or all of a deleted page
+    let written_set: HashSet<&PathBuf> = written.iter().collect();
+    let stale: Vec<PathBuf> = std::mem::replace(&mut state.pages, page_files)
+        .into_values()
+        .flatten()
+        .filter(|path| !written_set.contains(path))
+        .collect();
+    state.outputs.extend(written);
+    for path in stale {
+        state.outputs.remove(&path);
+        remove_output(&path)?;
+    }
+    for path in expired_outputs {
+        remove_outputs(&mut state, &path)?;
+    }
+    if config().precompress {
+        timings::time(Phase::Compressing, || compress::precompress(&mut state))?;
+    }
+    for (url, checksum, html) in to_mention {
+        // ones that didn't all go out are tried again next time
+        if webmentions::send(&config().absolute_url(&url), &html) {
+            state.mentioned.insert(url, checksum);
+        }
+    }
+    state.mentioned.retain(|url, _| checksums.contains_key(url));
+    state.checksums = checksums;
+    timings::time(Phase::State, || state.save(&*STATE_PATH))?;
+
+    if let Some(command) = &config().hooks.post_page {
+        for (slug, url) in &changed {
+            let page_dir = WEBSITE_DIR.join(url.trim_matches('/'));
+            let page_dir = page_dir.to_string_lossy();
+            let env = [
+                ("BLOG_SLUG", &**slug),
+                ("BLOG_URL", url),
+                ("BLOG_PAGE_DIR", &page_dir),
+            ];
+            hooks::run("post_page", command, &env)?;
+        }
+    }
+    if let Some(command) = &config().hooks.post_build {
+        let slugs: Vec<&str> = changed.iter().map(|(slug, _)| slug.as_str()).collect();
+        hooks::run(
+            "post_build",
+            command,
+            &[("BLOG_CHANGED", &slugs.join("\n"))],
+        )?;
+    }
+
+    if args.timings {
+        timings::report();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_stay_inside() {
+        assert!(stays_inside(Path::new("feed/index.xml")));
+        assert!(stays_inside(Path::new("./a")));
+        assert!(!stays_inside(Path::new("")));
+        assert!(!stays_inside(Path::new(".")));
+        assert!(!stays_inside(Path::new("../a")));
+        assert!(!stays_inside(Path::new("a/../../b")));
+        assert!(!stays_inside(Path::new("/etc/passwd")));
+    }
+
+    #[test]
+    fn details_renders_without_dangerous_html() {
+        load_default_config();
+        let options = MarkdownConfig {
+            allow_dangerous_html: false,
+            ..MarkdownConfig::default()
+        };
+        let markdown =
+            "<b>raw</b>\n\n{% details(summary=\"Spoilers\") %}\nIt was *his* sled.\n{% end %}\n";
+        let rendered =
+            shortcodes::render_shortcodes(markdown, &Tera::default(), &tera::Context::new())
+                .map_err(|err| err.in_file(Path::new("test.md"), 1))
+                .unwrap();
+        let html = rendered.insert_html(&markdown_to_html(&rendered.markdown, &options));
+        assert!(
+            html.contains("<details><summary>Spoilers</summary>"),
+            "{html}"
+        );
+        assert!(html.contains("<em>his</em>"), "{html}");
+        assert!(html.contains("</details>"), "{html}");
+        // other html is still escaped
+        assert!(html.contains("&lt;b&gt;raw"), "{html}");
+    }
+}
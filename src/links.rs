@@ -0,0 +1,286 @@
+//! Checks that links from one page of the site to another go somewhere, and
+//! that links to other sites still work.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use chrono::Utc;
+use kuchikiki::traits::TendrilSink;
+use log::info;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{config, staging::Staging, WEBSITE_DIR};
+
+/// Attributes that point at other files.
+const LINK_ATTRIBUTES: [(&str, &str); 5] = [
+    ("a", "href"),
+    ("link", "href"),
+    ("img", "src"),
+    ("script", "src"),
+    ("source", "src"),
+];
+
+/// Every link in `html`.
+fn links_in(html: &str) -> Vec<String> {
+    let document = kuchikiki::parse_html().one(html);
+    let mut links = Vec::new();
+    for (tag, attribute) in LINK_ATTRIBUTES {
+        let selector = format!("{tag}[{attribute}]");
+        for element in document.select(&selector).unwrap() {
+            let attributes = element.attributes.borrow();
+            links.push(attributes.get(attribute).unwrap_or_default().to_owned());
+        }
+    }
+    links
+}
+
+/// Finds links in the html files `staging` is going to write that don't go to
+/// a file on the site, as (html file, link) pairs.
+pub fn find_broken_links(staging: &Staging) -> Vec<(PathBuf, String)> {
+    let mut broken = Vec::new();
+    for (path, html) in staging.html_files() {
+        let dir = path.parent().unwrap_or(&WEBSITE_DIR);
+        for link in links_in(html) {
+            let Some(target) = link_target(&link, dir) else {
+                continue;
+            };
+            if !exists(&target, staging) {
+                broken.push((path.to_owned(), link));
+            }
+        }
+    }
+    broken
+}
+
+/// Works out which file under `website/` a link from a page in `dir` is to,
+/// if it's to this site at all.
+pub fn link_target<'a>(link: &'a str, dir: &Path) -> Option<PathBuf> {
+    let config = config();
+    let base_url = config.base_url.trim_end_matches('/');
+    let base_path = config.root_relative_url("");
+    let base_path = base_path.trim_end_matches('/');
+    let under = |link: &'a str, base: &str| match link.strip_prefix(base) {
+        Some(path) if path.is_empty() || path.starts_with('/') => Some(path),
+        _ => None,
+    };
+    let link = match under(link, base_url) {
+        Some(path) => path,
+        // root-relative links have `base_url`'s path in front
+        None if link.starts_with('/') && !link.starts_with("//") => {
+            under(link, base_path).unwrap_or(link)
+        }
+        None => link,
+    };
+    let is_elsewhere = link.contains(':') || link.starts_with("//");
+    let path = link.split(['?', '#']).next().unwrap_or_default();
+    if is_elsewhere || path.is_empty() && !link.is_empty() {
+        return None;
+    }
+
+    let target = match path.strip_prefix('/') {
+        Some(path) => WEBSITE_DIR.join(path),
+        None => dir.join(path),
+    };
+    // tidy up `..`s, without going above `website/`
+    let mut normalized = PathBuf::new();
+    for component in target.components() {
+        match component {
+            Component::ParentDir => {
+                if normalized != *WEBSITE_DIR {
+                    normalized.pop();
+                }
+            }
+            Component::CurDir => {}
+            component => normalized.push(component),
+        }
+    }
+    if path.is_empty() || path.ends_with('/') {
+        normalized.push("index.html");
+    }
+    Some(normalized)
+}
+
+fn exists(target: &Path, staging: &Staging) -> bool {
+    let is_file = |path: &Path| staging.will_write(path) || path.is_file();
+    // `/about` works as well as `/about/`
+    is_file(target) || target.extension().is_none() && is_file(&target.join("index.html"))
+}
+
+/// How a link to another site was, last time it was checked.
+#[derive(Deserialize, Serialize, Clone)]
+struct LinkStatus {
+    /// e.g. `200`, `404`, or why it couldn't be fetched.
+    status: String,
+    ok: bool,
+    /// Unix timestamp.
+    checked: i64,
+}
+
+/// Working links aren't checked again for this long.
+const RECHECK_AFTER_SECS: i64 = 7 * 24 * 60 * 60;
+/// Time between requests to the same site.
+const DELAY_PER_HOST: Duration = Duration::from_secs(1);
+const MAX_THREADS: usize = 8;
+
+/// Checks every link to another site in the built pages, printing the ones
+/// that don't work, by page. Results are kept in `cache_path`.
+pub fn check_external_links(cache_path: &Path) -> Result<()> {
+    // pages linking to each url
+    let mut pages_by_url: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for entry in WalkDir::new(&*WEBSITE_DIR)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        if path.extension().is_none_or(|e| e != "html") {
+            continue;
+        }
+        let html = fs::read_to_string(path)?;
+        for link in links_in(&html) {
+            if config().is_external(&link) && !link.starts_with("//") {
+                let pages = pages_by_url.entry(link).or_default();
+                if !pages.iter().any(|page| page == path) {
+                    pages.push(path.to_owned());
+                }
+            }
+        }
+    }
+
+    let mut statuses: BTreeMap<String, LinkStatus> = fs::read_to_string(cache_path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
+    let now = Utc::now().timestamp();
+
+    // urls that need checking, by host, so each host can be checked in turn
+    let mut by_host: HashMap<String, Vec<String>> = HashMap::new();
+    for url in pages_by_url.keys() {
+        let fresh = statuses
+            .get(url)
+            .is_some_and(|status| status.ok && now - status.checked < RECHECK_AFTER_SECS);
+        if !fresh {
+            let host = url.split('/').nth(2).unwrap_or_default().to_owned();
+            by_host.entry(host).or_default().push(url.clone());
+        }
+    }
+    let checking: usize = by_host.values().map(Vec::len).sum();
+    info!(
+        "Checking {checking} of {} links to other sites",
+        pages_by_url.len()
+    );
+
+    let hosts = Mutex::new(by_host.into_values().collect::<Vec<_>>());
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..MAX_THREADS {
+            scope.spawn(|| loop {
+                let Some(urls) = hosts.lock().unwrap().pop() else {
+                    break;
+                };
+                for (i, url) in urls.into_iter().enumerate() {
+                    if i > 0 {
+                        thread::sleep(DELAY_PER_HOST);
+                    }
+                    let status = check_url(&url);
+                    results.lock().unwrap().push((url, status));
+                }
+            });
+        }
+    });
+    statuses.extend(results.into_inner().unwrap());
+
+    if let Some(parent) = cache_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(cache_path, serde_json::to_string_pretty(&statuses)?)?;
+
+    // dead links, by page
+    let mut dead: BTreeMap<&PathBuf, Vec<(&String, &str)>> = BTreeMap::new();
+    for (url, pages) in &pages_by_url {
+        let Some(status) = statuses.get(url).filter(|status| !status.ok) else {
+            continue;
+        };
+        for page in pages {
+            dead.entry(page).or_default().push((url, &status.status));
+        }
+    }
+    for (page, links) in &dead {
+        println!("{}", page.to_string_lossy());
+        for (url, status) in links {
+            println!("  {url} ({status})");
+        }
+    }
+
+    let dead_count = statuses
+        .iter()
+        .filter(|(url, status)| !status.ok && pages_by_url.contains_key(*url))
+        .count();
+    if dead_count > 0 {
+        bail!("{dead_count} dead links");
+    }
+    info!("All links work");
+    Ok(())
+}
+
+fn check_url(url: &str) -> LinkStatus {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(15))
+        .build();
+    // some sites don't do HEAD, so fall back on GET
+    let response = match agent.head(url).call() {
+        Err(ureq::Error::Status(405 | 403 | 400, _)) => agent.get(url).call(),
+        response => response,
+    };
+    let (status, ok) = match response {
+        Ok(response) => (response.status().to_string(), true),
+        Err(ureq::Error::Status(code, _)) => (code.to_string(), false),
+        Err(ureq::Error::Transport(err)) => (err.kind().to_string(), false),
+    };
+    LinkStatus {
+        status,
+        ok,
+        checked: Utc::now().timestamp(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_link_targets() {
+        crate::load_default_config();
+        let dir = WEBSITE_DIR.join("posts/a");
+        let target = |link: &str| link_target(link, &dir);
+
+        assert_eq!(
+            target("/about/"),
+            Some(WEBSITE_DIR.join("about/index.html"))
+        );
+        assert_eq!(
+            target("cat.png?v=2#top"),
+            Some(WEBSITE_DIR.join("posts/a/cat.png"))
+        );
+        assert_eq!(
+            target("../b/"),
+            Some(WEBSITE_DIR.join("posts/b/index.html"))
+        );
+        // not above `website/`
+        assert_eq!(
+            target("../../../../etc/passwd"),
+            Some(WEBSITE_DIR.join("etc/passwd"))
+        );
+        assert_eq!(target("https://example.org/"), None);
+        assert_eq!(target("//example.org/"), None);
+        assert_eq!(target("mailto:me@example.org"), None);
+        assert_eq!(target("#top"), None);
+    }
+}
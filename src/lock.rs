@@ -0,0 +1,127 @@
+//! A lock file that keeps two builds from running at once, which would race
+//! on the output directory and leave `state.json` half one, half the other.
+
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Result};
+use log::warn;
+
+/// A lock older than this is from a build that died without removing it, when
+/// its process can't be checked.
+const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Held until it's dropped.
+pub struct Lock {
+    path: PathBuf,
+}
+
+impl Lock {
+    /// Takes the lock at `path`, holding this process's id. A lock left by a
+    /// build that's no longer running is taken over.
+    pub fn acquire<P: AsRef<Path>>(path: P) -> Result<Lock> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+            {
+                Ok(mut file) => {
+                    write!(file, "{}", process::id())?;
+                    return Ok(Lock {
+                        path: path.to_owned(),
+                    });
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    let pid = fs::read_to_string(path).unwrap_or_default();
+                    let pid = pid.trim();
+                    if !is_stale(path, pid) {
+                        bail!(
+                            "another build (process {pid}) is running; if it isn't, delete {}",
+                            path.to_string_lossy()
+                        );
+                    }
+                    warn!("removing stale lock {}", path.to_string_lossy());
+                    match fs::remove_file(path) {
+                        Ok(()) => {}
+                        Err(err) if err.kind() == ErrorKind::NotFound => {}
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // `blog clean` takes the lock's directory with it
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Whether the lock at `path`, held by process `pid`, was left behind.
+fn is_stale(path: &Path, pid: &str) -> bool {
+    if let Some(running) = pid.parse().ok().and_then(is_running) {
+        return !running;
+    }
+    // a lock that's just been made may not have its id written yet, so only
+    // an old one counts
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age > STALE_AFTER)
+}
+
+/// Whether process `pid` is running, if that can be checked.
+#[cfg(unix)]
+fn is_running(pid: u32) -> Option<bool> {
+    let pid = libc::pid_t::try_from(pid).ok()?;
+    // signal 0 checks that the process exists without doing anything to it
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return Some(true);
+    }
+    match std::io::Error::last_os_error().raw_os_error() {
+        // it's there, just someone else's
+        Some(libc::EPERM) => Some(true),
+        Some(libc::ESRCH) => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> Option<bool> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_old_locks_whose_build_is_running() {
+        let path = std::env::temp_dir().join(format!("blog-lock-{}", process::id()));
+        fs::write(&path, process::id().to_string()).unwrap();
+        let long_ago = SystemTime::now() - 2 * STALE_AFTER;
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(long_ago)
+            .unwrap();
+        assert!(!is_stale(&path, &process::id().to_string()));
+        assert!(is_stale(&path, ""));
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,46 @@
+//! Where `log` messages go: progress to stdout, warnings and errors to
+//! stderr. Only the blog's own messages are shown, not its dependencies'.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// How many warnings have been logged.
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+            && metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            Level::Error => eprintln!("error: {}", record.args()),
+            Level::Warn => {
+                WARNINGS.fetch_add(1, Ordering::Relaxed);
+                eprintln!("warning: {}", record.args());
+            }
+            _ => println!("{}", record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sets up logging, showing messages up to `level`. Warnings are always
+/// shown.
+pub fn init(level: LevelFilter) {
+    let _ = log::set_logger(&Logger);
+    log::set_max_level(level.max(LevelFilter::Warn));
+}
+
+/// How many warnings have been logged so far.
+pub fn warnings() -> usize {
+    WARNINGS.load(Ordering::Relaxed)
+}
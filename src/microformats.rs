@@ -0,0 +1,147 @@
+//! Microformats2 classes for rendered pages, so that IndieWeb readers and
+//! webmention receivers can tell what a page's title, date, author, and
+//! contents are. See <https://microformats.org/wiki/h-entry>.
+
+use kuchikiki::{traits::TendrilSink, NodeRef};
+
+use crate::{
+    config,
+    html::{escape, get_body_children_of_document},
+    PageContext,
+};
+
+/// Marks up `document`, a rendered page, as an `h-entry`. Templates are left
+/// as they are, so elements are found by what's in them: the page's title,
+/// date, authors, and contents. Anything that can't be found is added,
+/// hidden.
+pub fn add_microformats(document: &NodeRef, page: &PageContext) {
+    let Some(entry) = ["article", "main", "body"]
+        .iter()
+        .find_map(|selector| document.select_first(selector).ok())
+        .map(|entry| entry.as_node().clone())
+    else {
+        return;
+    };
+    add_class(&entry, "h-entry");
+
+    let has_text = |node: &NodeRef, text: &str| collapse_whitespace(&node.text_contents()) == text;
+
+    let title = collapse_whitespace(&page.title);
+    if let Some(heading) = entry
+        .select("h1, h2")
+        .unwrap()
+        .find(|heading| has_text(heading.as_node(), &title))
+    {
+        add_class(heading.as_node(), "p-name");
+    }
+
+    // the innermost element with all of the contents in it
+    let contents = kuchikiki::parse_html().one(page.contents.as_str());
+    let contents = collapse_whitespace(&contents.text_contents());
+    if !contents.is_empty() {
+        if let Some(element) = entry
+            .inclusive_descendants()
+            .filter(|node| node.as_element().is_some())
+            .filter(|node| collapse_whitespace(&node.text_contents()).contains(&contents))
+            .last()
+        {
+            add_class(&element, "e-content");
+        }
+    }
+
+    if let Some(iso_date) = &page.iso_date {
+        let date = collapse_whitespace(&page.date);
+        let element = entry
+            .descendants()
+            .filter(|node| node.as_element().is_some())
+            .find(|node| !date.is_empty() && has_text(node, &date));
+        match element {
+            Some(element) if is_element(&element, "time") => {
+                add_class(&element, "dt-published");
+                let element = element.as_element().unwrap();
+                let mut attributes = element.attributes.borrow_mut();
+                if !attributes.contains("datetime") {
+                    attributes.insert("datetime", iso_date.clone());
+                }
+            }
+            // dates as written can be anything, like `5/18`, so the element's
+            // text is wrapped in a `<time>` with the date as it's parsed
+            Some(element) => {
+                let time = new_element(&format!(
+                    r#"<time class="dt-published" datetime="{iso_date}"></time>"#
+                ));
+                for child in element.children().collect::<Vec<_>>() {
+                    time.append(child);
+                }
+                element.append(time);
+            }
+            None => entry.append(new_element(&format!(
+                r#"<data class="dt-published" value="{iso_date}" hidden></data>"#
+            ))),
+        }
+    }
+
+    let config = config();
+    let authors: Vec<(&str, Option<&str>)> = match page.authors.is_empty() {
+        true => vec![(config.author.as_str(), Some(config.base_url.as_str()))],
+        false => page
+            .authors
+            .iter()
+            .map(|author| {
+                let url = author.page_url.as_deref().or(author.author.url.as_deref());
+                (author.author.name.as_str(), url)
+            })
+            .collect(),
+    };
+    for (name, url) in authors {
+        let name = collapse_whitespace(name);
+        if name.is_empty() {
+            continue;
+        }
+        if let Some(link) = entry
+            .select("a")
+            .unwrap()
+            .find(|link| has_text(link.as_node(), &name))
+        {
+            add_class(link.as_node(), "p-author h-card");
+            continue;
+        }
+        let href = match url.filter(|url| !url.is_empty()) {
+            Some(url) => format!(r#" href="{}""#, escape(url)),
+            None => String::new(),
+        };
+        entry.append(new_element(&format!(
+            r#"<a class="p-author h-card"{href} hidden>{}</a>"#,
+            escape(&name)
+        )));
+    }
+}
+
+fn add_class(node: &NodeRef, class: &str) {
+    let Some(element) = node.as_element() else {
+        return;
+    };
+    let mut attributes = element.attributes.borrow_mut();
+    let classes = match attributes.get("class") {
+        Some(classes) if !classes.is_empty() => format!("{classes} {class}"),
+        _ => class.to_owned(),
+    };
+    attributes.insert("class", classes);
+}
+
+fn is_element(node: &NodeRef, name: &str) -> bool {
+    node.as_element()
+        .is_some_and(|element| element.name.local.as_ref() == name)
+}
+
+/// Parses a single element from `html`.
+fn new_element(html: &str) -> NodeRef {
+    let document = kuchikiki::parse_html().one(html);
+    let element = get_body_children_of_document(&document).next().unwrap();
+    element.detach();
+    element
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,266 @@
+//! Plain text versions of pages, for reading with `curl` and for search.
+
+use kuchikiki::NodeRef;
+
+use crate::html::get_body_children_of_document;
+
+/// How wide lines are wrapped.
+const WIDTH: usize = 72;
+
+/// The text of a page's body, wrapped, with the markup stripped. Headings are
+/// underlined, list items start with `- ` (or their number, in ordered
+/// lists), and quotes with `> `.
+pub fn to_plain_text(document: &NodeRef) -> String {
+    let mut writer = Writer::default();
+    for node in get_body_children_of_document(document) {
+        writer.node(&node, "");
+    }
+    writer.flush("");
+
+    let mut text = String::new();
+    let mut last_tight = false;
+    for (i, (block, tight)) in writer.blocks.iter().enumerate() {
+        if i > 0 {
+            // list items and table rows stay together
+            text.push_str(if *tight && last_tight { "\n" } else { "\n\n" });
+        }
+        text.push_str(block);
+        last_tight = *tight;
+    }
+    text.push('\n');
+    text
+}
+
+#[derive(Default)]
+struct Writer {
+    /// Paragraphs so far, and whether each is a list item or table row.
+    blocks: Vec<(String, bool)>,
+    /// Text not yet in a paragraph.
+    inline: String,
+    /// What the next paragraph starts with instead of its prefix, e.g. a
+    /// list item's `- `.
+    marker: Option<String>,
+    /// Whether the next paragraph is a list item or table row.
+    tight: bool,
+}
+
+impl Writer {
+    fn node(&mut self, node: &NodeRef, prefix: &str) {
+        if let Some(text) = node.as_text() {
+            self.inline.push_str(&text.borrow());
+            return;
+        }
+        let Some(element) = node.as_element() else {
+            return;
+        };
+        let children = |writer: &mut Writer, prefix: &str| {
+            for child in node.children() {
+                writer.node(&child, prefix);
+            }
+        };
+
+        match element.name.local.as_ref() {
+            "script" | "style" | "svg" | "template" | "head" => {}
+            "br" => self.inline.push('\n'),
+            "img" => {
+                let attributes = element.attributes.borrow();
+                if let Some(alt) = attributes.get("alt").filter(|alt| !alt.is_empty()) {
+                    self.inline.push_str(&format!("[{alt}]"));
+                }
+            }
+            "pre" => {
+                self.flush(prefix);
+                let code = node.text_contents();
+                let lines: Vec<String> = code
+                    .trim_end()
+                    .lines()
+                    .map(|line| format!("{prefix}    {line}").trim_end().to_owned())
+                    .collect();
+                self.blocks.push((lines.join("\n"), false));
+            }
+            heading @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6") => {
+                self.flush(prefix);
+                children(self, prefix);
+                let text = collapse_whitespace(&std::mem::take(&mut self.inline));
+                if text.is_empty() {
+                    return;
+                }
+                let underline = match heading {
+                    "h1" => "=",
+                    "h2" => "-",
+                    _ => "",
+                };
+                let mut block = format!("{prefix}{text}");
+                if !underline.is_empty() {
+                    let length = text.chars().count().min(WIDTH);
+                    block.push_str(&format!("\n{prefix}{}", underline.repeat(length)));
+                }
+                self.blocks.push((block, false));
+            }
+            "li" => {
+                self.flush(prefix);
+                let marker = list_marker(node);
+                let indent = format!("{prefix}{}", " ".repeat(marker.chars().count()));
+                self.marker = Some(format!("{prefix}{marker}"));
+                self.tight = true;
+                children(self, &indent);
+                self.flush(&indent);
+            }
+            "tr" => {
+                self.flush(prefix);
+                let cells: Vec<String> = node
+                    .children()
+                    .filter(|child| child.as_element().is_some())
+                    .map(|cell| cell_text(&cell))
+                    .collect();
+                self.blocks
+                    .push((format!("{prefix}{}", cells.join(" | ")), true));
+            }
+            "blockquote" => {
+                self.flush(prefix);
+                let prefix = format!("{prefix}> ");
+                children(self, &prefix);
+                self.flush(&prefix);
+            }
+            "p" | "div" | "ul" | "ol" | "table" | "figure" | "figcaption" | "details"
+            | "summary" | "section" | "article" | "aside" | "header" | "footer" | "nav" | "dl"
+            | "dt" | "dd" | "hr" => {
+                self.flush(prefix);
+                children(self, prefix);
+                self.flush(prefix);
+            }
+            _ => children(self, prefix),
+        }
+    }
+
+    /// Wraps the text so far into a paragraph, each line starting with
+    /// `prefix`.
+    fn flush(&mut self, prefix: &str) {
+        let inline = std::mem::take(&mut self.inline);
+        let mut lines = Vec::new();
+        for text in inline.split('\n') {
+            let first = match (&self.marker, lines.is_empty()) {
+                (Some(marker), true) => marker.clone(),
+                _ => prefix.to_owned(),
+            };
+            wrap(&collapse_whitespace(text), &first, prefix, &mut lines);
+        }
+        if lines.iter().any(|line| line.trim() != prefix.trim()) {
+            self.blocks.push((lines.join("\n"), self.tight));
+            self.marker = None;
+            self.tight = false;
+        }
+    }
+}
+
+/// `- `, or the item's number and a dot if it's in an `ol`, counting from
+/// the list's `start`.
+fn list_marker(item: &NodeRef) -> String {
+    let Some(list) = item.parent() else {
+        return "- ".to_owned();
+    };
+    let is_ordered = list
+        .as_element()
+        .is_some_and(|element| element.name.local.as_ref() == "ol");
+    if !is_ordered {
+        return "- ".to_owned();
+    }
+    let start: i64 = list
+        .as_element()
+        .and_then(|element| {
+            element
+                .attributes
+                .borrow()
+                .get("start")?
+                .trim()
+                .parse()
+                .ok()
+        })
+        .unwrap_or(1);
+    let before = item
+        .preceding_siblings()
+        .filter(|sibling| {
+            sibling
+                .as_element()
+                .is_some_and(|element| element.name.local.as_ref() == "li")
+        })
+        .count() as i64;
+    format!("{}. ", start + before)
+}
+
+/// A table cell's text on one line, with line breaks as spaces.
+fn cell_text(cell: &NodeRef) -> String {
+    let mut text = String::new();
+    for node in cell.descendants() {
+        if let Some(contents) = node.as_text() {
+            text.push_str(&contents.borrow());
+        } else if node
+            .as_element()
+            .is_some_and(|element| element.name.local.as_ref() == "br")
+        {
+            text.push(' ');
+        }
+    }
+    collapse_whitespace(&text)
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Greedily wraps `text` into `lines`, the first starting with `first` and
+/// the rest with `prefix`.
+fn wrap(text: &str, first: &str, prefix: &str, lines: &mut Vec<String>) {
+    if text.is_empty() {
+        return;
+    }
+    let mut line = first.to_owned();
+    let mut empty = true;
+    for word in text.split(' ') {
+        if !empty && line.chars().count() + 1 + word.chars().count() > WIDTH {
+            lines.push(std::mem::replace(&mut line, prefix.to_owned()));
+            empty = true;
+        }
+        if !empty {
+            line.push(' ');
+        }
+        line.push_str(word);
+        empty = false;
+    }
+    lines.push(line);
+}
+
+#[cfg(test)]
+mod tests {
+    use kuchikiki::traits::TendrilSink;
+
+    use super::*;
+
+    fn plain_text(html: &str) -> String {
+        to_plain_text(&kuchikiki::parse_html().one(html))
+    }
+
+    #[test]
+    fn numbers_ordered_lists() {
+        assert_eq!(
+            plain_text("<ul><li>one</li><li>two</li></ul>"),
+            "- one\n- two\n"
+        );
+        assert_eq!(
+            plain_text("<ol><li>one</li><li>two</li></ol>"),
+            "1. one\n2. two\n"
+        );
+        assert_eq!(
+            plain_text("<ol start=\"9\"><li>nine</li><li><p>ten</p><p>more</p></li></ol>"),
+            "9. nine\n10. ten\n\n    more\n"
+        );
+    }
+
+    #[test]
+    fn line_breaks_in_table_cells_are_spaces() {
+        assert_eq!(
+            plain_text("<table><tr><td>a<br>b</td><td>c</td></tr></table>"),
+            "a b | c\n"
+        );
+    }
+}
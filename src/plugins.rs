@@ -0,0 +1,56 @@
+//! Plugins: whole-document transforms and extra files, for programs that
+//! use `blog` as a library. Unlike shortcodes, which replace bits of a page,
+//! a plugin sees all of it.
+//!
+//! ```no_run
+//! struct Shout;
+//!
+//! impl blog::plugins::Plugin for Shout {
+//!     fn transform_markdown(&self, _: &blog::Page, markdown: String) -> anyhow::Result<String> {
+//!         Ok(markdown.to_uppercase())
+//!     }
+//! }
+//!
+//! blog::plugins::register(Shout);
+//! blog::build(&blog::cli::Args::default()).unwrap();
+//! ```
+//!
+//! Sites can also have plugins compiled to WebAssembly in `plugins/`; see
+//! [`wasm`].
+
+use std::{path::PathBuf, sync::Mutex};
+
+use anyhow::Result;
+
+use crate::{Page, PageSummary};
+
+pub mod wasm;
+
+/// Each method does nothing by default, so a plugin only has the ones it
+/// needs.
+pub trait Plugin: Send {
+    /// Changes a page's markdown before its shortcodes are rendered.
+    fn transform_markdown(&self, _page: &Page, markdown: String) -> Result<String> {
+        Ok(markdown)
+    }
+
+    /// Changes a page's html once its template's been rendered.
+    fn transform_html(&self, _page: &Page, html: String) -> Result<String> {
+        Ok(html)
+    }
+
+    /// Files to write, by path under the output directory, given every page
+    /// in the index.
+    fn outputs(&self, _posts: &[PageSummary]) -> Result<Vec<(PathBuf, String)>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Plugins, in the order they were registered, which is the order they run
+/// in.
+pub(crate) static PLUGINS: Mutex<Vec<Box<dyn Plugin>>> = Mutex::new(Vec::new());
+
+/// Adds `plugin` to every build from now on.
+pub fn register<P: Plugin + 'static>(plugin: P) {
+    PLUGINS.lock().unwrap().push(Box::new(plugin));
+}
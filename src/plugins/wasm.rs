@@ -0,0 +1,191 @@
+//! Plugins compiled to WebAssembly, from `plugins/*.wasm`, so a site can
+//! have them without a program of its own around `blog`.
+//!
+//! A module exports its `memory`, an `alloc(len: i32) -> i32` that the input
+//! is written to, and any of `transform_markdown`, `transform_html`, and
+//! `outputs`. Each of those takes the input's `(ptr: i32, len: i32)` and
+//! returns its output as an `i64`, the pointer in the high 32 bits and the
+//! length in the low ones.
+//!
+//! The transforms get `{"page": {...}, "text": "..."}` as json and return the
+//! new text. `outputs` gets the index's pages as a json list and returns a
+//! json object of file contents, by path under the output directory.
+//! Modules can't import anything, so all they can do is compute.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Context, Result};
+use wasmi::{Config, Engine, Instance, Linker, Module, Store};
+
+use super::Plugin;
+use crate::{Page, PageSummary};
+
+/// How many instructions, roughly, a module gets for each call before it's
+/// stopped. Tests run out sooner.
+const FUEL: u64 = if cfg!(test) { 1_000_000 } else { 1_000_000_000 };
+
+pub struct WasmPlugin {
+    path: PathBuf,
+    store: Mutex<Store<()>>,
+    instance: Instance,
+}
+
+impl WasmPlugin {
+    pub fn load(path: &Path) -> Result<WasmPlugin> {
+        WasmPlugin::new(path, &fs::read(path)?)
+    }
+
+    /// The plugin in `wasm`, binary or text, from `path`.
+    fn new(path: &Path, wasm: &[u8]) -> Result<WasmPlugin> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm)?;
+        let mut store = Store::new(&engine, ());
+        store.set_fuel(FUEL)?;
+        let instance = Linker::new(&engine).instantiate_and_start(&mut store, &module)?;
+        Ok(WasmPlugin {
+            path: path.to_owned(),
+            store: Mutex::new(store),
+            instance,
+        })
+    }
+
+    /// Calls `export` with `input`, if the module has it.
+    fn call(&self, export: &str, input: &[u8]) -> Result<Option<Vec<u8>>> {
+        let mut store = self.store.lock().unwrap();
+        let Ok(func) = self
+            .instance
+            .get_typed_func::<(i32, i32), i64>(&*store, export)
+        else {
+            return Ok(None);
+        };
+        store.set_fuel(FUEL)?;
+        let memory = self
+            .instance
+            .get_memory(&*store, "memory")
+            .context("it doesn't export its `memory`")?;
+        let alloc = self
+            .instance
+            .get_typed_func::<i32, i32>(&*store, "alloc")
+            .context("it doesn't export `alloc`")?;
+
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut *store, len)?;
+        memory.write(&mut *store, ptr as u32 as usize, input)?;
+        let result = func.call(&mut *store, (ptr, len))?;
+        let (ptr, len) = ((result >> 32) as u32 as usize, result as u32 as usize);
+        let mut output = vec![0; len];
+        memory.read(&*store, ptr, &mut output)?;
+        Ok(Some(output))
+    }
+
+    fn transform(&self, export: &str, page: &Page, text: String) -> Result<String> {
+        let input = serde_json::json!({ "page": page.summary(), "text": text });
+        let output = self
+            .call(export, input.to_string().as_bytes())
+            .with_context(|| format!("{}: `{export}` failed", self.path.to_string_lossy()))?;
+        match output {
+            Some(output) => Ok(String::from_utf8(output)?),
+            None => Ok(text),
+        }
+    }
+}
+
+impl Plugin for WasmPlugin {
+    fn transform_markdown(&self, page: &Page, markdown: String) -> Result<String> {
+        self.transform("transform_markdown", page, markdown)
+    }
+
+    fn transform_html(&self, page: &Page, html: String) -> Result<String> {
+        self.transform("transform_html", page, html)
+    }
+
+    fn outputs(&self, posts: &[PageSummary]) -> Result<Vec<(PathBuf, String)>> {
+        let path = self.path.to_string_lossy();
+        let output = self
+            .call("outputs", &serde_json::to_vec(posts)?)
+            .with_context(|| format!("{path}: `outputs` failed"))?;
+        let Some(output) = output else {
+            return Ok(Vec::new());
+        };
+        let files: BTreeMap<PathBuf, String> = serde_json::from_slice(&output)
+            .map_err(|err| anyhow!("{path}: `outputs` didn't return a json object: {err}"))?;
+        Ok(files.into_iter().collect())
+    }
+}
+
+/// The plugins in `dir`, by filename.
+pub fn load_dir(dir: &Path) -> Result<Vec<WasmPlugin>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "wasm") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| {
+            WasmPlugin::load(path)
+                .with_context(|| format!("cannot load plugin {}", path.to_string_lossy()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers `transform_markdown` with what's written at 0, and loops
+    /// forever in `transform_html`.
+    const MODULE: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (data (i32.const 0) "hello")
+            (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+            (func (export "transform_markdown") (param i32 i32) (result i64)
+                (i64.const 5))
+            (func (export "transform_html") (param i32 i32) (result i64)
+                (loop (br 0))
+                (i64.const 0)))
+    "#;
+
+    fn plugin() -> WasmPlugin {
+        WasmPlugin::new(Path::new("test.wasm"), MODULE.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn calls_exports_with_input_in_memory() {
+        let output = plugin().call("transform_markdown", b"input").unwrap();
+        assert_eq!(output.as_deref(), Some(&b"hello"[..]));
+    }
+
+    #[test]
+    fn leaves_out_missing_exports() {
+        assert!(plugin().call("outputs", b"[]").unwrap().is_none());
+    }
+
+    #[test]
+    fn stops_runaway_calls() {
+        let plugin = plugin();
+        assert!(plugin.call("transform_html", b"").is_err());
+        // and the next call gets its fuel back
+        assert!(plugin.call("transform_markdown", b"").unwrap().is_some());
+    }
+
+    #[test]
+    fn refuses_imports() {
+        let module = r#"(module (import "env" "f" (func)))"#;
+        assert!(WasmPlugin::new(Path::new("test.wasm"), module.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use std::{collections::BTreeMap, fs, path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result};
+use kuchikiki::traits::TendrilSink;
+use serde::{Deserialize, Serialize};
+
+use log::warn;
+
+/// What a link preview card shows about a page elsewhere.
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct LinkPreview {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+}
+
+/// Link previews fetched by earlier builds, keyed by url, kept in
+/// `.cache/previews.json` so each page is only fetched once.
+pub struct PreviewCache {
+    path: PathBuf,
+    previews: BTreeMap<String, LinkPreview>,
+    changed: bool,
+}
+
+impl PreviewCache {
+    /// Loads the cache, starting over if it's missing or unreadable.
+    pub fn load(path: PathBuf) -> PreviewCache {
+        let json = fs::read_to_string(&path).unwrap_or_default();
+        let previews = serde_json::from_str(&json).unwrap_or_else(|_| {
+            if !json.is_empty() {
+                warn!("cannot parse {}", path.to_string_lossy());
+            }
+            BTreeMap::new()
+        });
+        PreviewCache {
+            path,
+            previews,
+            changed: false,
+        }
+    }
+
+    /// Returns the cached preview for `url`, or fetches it.
+    pub fn get_or_fetch(&mut self, url: &str) -> Result<LinkPreview> {
+        if let Some(preview) = self.previews.get(url) {
+            return Ok(preview.clone());
+        }
+        let preview = fetch(url).context("cannot fetch link preview")?;
+        self.previews.insert(url.to_owned(), preview.clone());
+        self.changed = true;
+        Ok(preview)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&self.previews)?)?;
+        Ok(())
+    }
+}
+
+/// Reads a page's title, description, and image, preferring its open graph
+/// tags.
+fn fetch(url: &str) -> Result<LinkPreview> {
+    let html = ureq::get(url)
+        .timeout(Duration::from_secs(10))
+        .call()?
+        .into_string()?;
+    let document = kuchikiki::parse_html().one(html);
+
+    let meta = |selector: &str| {
+        let tag = document.select_first(selector).ok()?;
+        let content = tag.attributes.borrow().get("content")?.trim().to_owned();
+        (!content.is_empty()).then_some(content)
+    };
+    let title = meta(r#"meta[property="og:title"]"#).or_else(|| {
+        let title = document.select_first("title").ok()?.text_contents();
+        let title = title.trim();
+        (!title.is_empty()).then(|| title.to_owned())
+    });
+    let description =
+        meta(r#"meta[property="og:description"]"#).or_else(|| meta(r#"meta[name="description"]"#));
+    // only absolute urls, a relative one would point at this blog
+    let image = meta(r#"meta[property="og:image"]"#)
+        .filter(|image| image.starts_with("https://") || image.starts_with("http://"));
+
+    Ok(LinkPreview {
+        title,
+        description,
+        image,
+    })
+}
@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+
+use crate::config::FrontMatterConfig;
+
+/// Finds the line `key` is set on in a file's front matter, counting from 1.
+pub fn key_line(file_contents: &str, key: &str) -> Option<usize> {
+    file_contents
+        .lines()
+        .enumerate()
+        // skip the opening `---`
+        .skip(1)
+        .take_while(|(_, line)| line.trim_end() != "---")
+        .find(|(_, line)| {
+            line.strip_prefix(key)
+                .is_some_and(|rest| rest.trim_start().starts_with(':'))
+        })
+        .map(|(i, _)| i + 1)
+}
+
+/// Checks a page's front matter against `[front_matter]` in the config,
+/// returning (key, problem) pairs.
+///
+/// `values` is all of the page's front matter, defaults included, and
+/// `extra` is whatever didn't match a known key.
+pub fn check(
+    values: &serde_json::Map<String, tera::Value>,
+    extra: &HashMap<String, tera::Value>,
+    config: &FrontMatterConfig,
+) -> Vec<(String, String)> {
+    let mut problems = Vec::new();
+
+    for key in &config.required {
+        if !values.contains_key(key) {
+            problems.push((
+                key.clone(),
+                format!("missing required front matter `{key}`"),
+            ));
+        }
+    }
+
+    if let Some(allowed) = &config.allowed_extra {
+        let mut unknown: Vec<_> = extra.keys().filter(|key| !allowed.contains(key)).collect();
+        unknown.sort();
+        for key in unknown {
+            problems.push((key.clone(), format!("unknown front matter `{key}`")));
+        }
+    }
+
+    problems
+}
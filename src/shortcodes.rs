@@ -0,0 +1,409 @@
+//! Shortcodes, like `{{ youtube(id="dQw4w9WgXcQ") }}` in a page's markdown.
+//!
+//! A shortcode is rendered with `templates/shortcodes/<name>.md` or
+//! `templates/shortcodes/<name>.html`. The output of a `.md` one goes into the
+//! markdown before it's parsed, so it can use markdown itself; the output of an
+//! `.html` one is put in after, so the markdown parser can't mangle it.
+//!
+//! Shortcodes can also wrap some markdown, which they get as `body`:
+//!
+//! ```text
+//! {% details(summary="Spoilers") %}
+//! It was his sled.
+//! {% end %}
+//! ```
+//!
+//! There are a few built-in shortcodes too, see [`builtin`].
+
+use std::{ops::Range, path::Path};
+
+use anyhow::{anyhow, bail, Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use tera::Tera;
+
+mod builtin;
+
+/// Where an html shortcode's body goes, as markdown, in what it renders.
+const BODY_SLOT: &str = "<!--body-->";
+
+/// A string, number, or boolean shortcode argument.
+const LITERAL: &str = r#""(?:[^"\\]|\\.)*"|'[^']*'|true|false|-?\d+(?:\.\d+)?"#;
+
+lazy_static! {
+    static ref SHORTCODE_RE: Regex = Regex::new(concat!(
+        r"\{\{\s*(\w+)\((.*?)\)\s*\}\}",
+        // or with a body
+        r"|\{%\s*(\w+)\((.*?)\)\s*%\}((?s:.*?))\{%\s*end\s*%\}",
+    ))
+    .unwrap();
+    static ref LITERAL_RE: Regex = Regex::new(LITERAL).unwrap();
+    static ref ARG_RE: Regex = Regex::new(&format!(
+        r"^\s*(\w+)\s*=\s*({LITERAL}|\[(?:\s*(?:{LITERAL})\s*,?)*\s*\])\s*(?:,|$)"
+    ))
+    .unwrap();
+    /// A placeholder, or one that was escaped because the markdown doesn't
+    /// allow html, which is in a paragraph of its own if it was on its own
+    /// line.
+    static ref PLACEHOLDER_RE: Regex = Regex::new(
+        r"<p>&lt;!--shortcode:(\d+)--&gt;</p>|(?:<|&lt;)!--shortcode:(\d+)--(?:>|&gt;)"
+    )
+    .unwrap();
+}
+
+/// Markdown with its shortcodes rendered, except html ones, which are left as
+/// placeholders until the markdown is turned into html.
+pub struct Rendered {
+    pub markdown: String,
+    /// What each html shortcode rendered.
+    pub html: Vec<String>,
+}
+
+impl Rendered {
+    /// Puts the output of html shortcodes into `html`, which was rendered
+    /// from `self.markdown`.
+    pub fn insert_html(&self, html: &str) -> String {
+        PLACEHOLDER_RE
+            .replace_all(html, |caps: &regex::Captures| {
+                let index = caps.get(1).or_else(|| caps.get(2)).unwrap();
+                let index: usize = index.as_str().parse().unwrap();
+                self.html.get(index).cloned().unwrap_or_default()
+            })
+            .into_owned()
+    }
+}
+
+/// A shortcode that couldn't be rendered, and where it is.
+pub struct ShortcodeError {
+    name: String,
+    /// Line and column in the markdown, counting from 1.
+    line: usize,
+    column: usize,
+    source: anyhow::Error,
+}
+
+impl ShortcodeError {
+    /// Turns this into an error pointing at the shortcode in `path`, where
+    /// the markdown starts on `first_line`.
+    pub fn in_file(self, path: &Path, first_line: usize) -> anyhow::Error {
+        let message = format!(
+            "{}:{}:{}: cannot render shortcode `{}`",
+            path.to_string_lossy(),
+            first_line + self.line - 1,
+            self.column,
+            self.name,
+        );
+        self.source.context(message)
+    }
+}
+
+/// Renders the shortcodes in `markdown`. Anything that looks like a shortcode
+/// but isn't one is left alone.
+pub fn render_shortcodes(
+    markdown: &str,
+    tera: &Tera,
+    context: &tera::Context,
+) -> Result<Rendered, ShortcodeError> {
+    let mut html = Vec::new();
+    let mut output = String::with_capacity(markdown.len());
+    let mut last = 0;
+    // shortcodes in code are being shown, not used
+    let code = code_ranges(markdown);
+
+    for caps in SHORTCODE_RE.captures_iter(markdown) {
+        let whole = caps.get(0).unwrap();
+        if code.iter().any(|range| range.contains(&whole.start())) {
+            continue;
+        }
+        let (name, args, body) = match caps.get(1) {
+            Some(name) => (name.as_str(), &caps[2], None),
+            None => (&caps[3], &caps[4], Some(&caps[5])),
+        };
+
+        let Some((renderer, is_markdown)) = find(name, tera) else {
+            continue;
+        };
+
+        let rendered = render_one(tera, &renderer, args, body, context).map_err(|source| {
+            let before = &markdown[..whole.start()];
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            ShortcodeError {
+                name: name.to_owned(),
+                line: before.matches('\n').count() + 1,
+                column: before[line_start..].chars().count() + 1,
+                source,
+            }
+        })?;
+
+        output.push_str(&markdown[last..whole.start()]);
+        match (is_markdown, body, rendered.split_once(BODY_SLOT)) {
+            (true, _, _) => output.push_str(&rendered),
+            // html around a body that's still markdown
+            (false, Some(body), Some((before, after))) => {
+                push_placeholder(&mut output, &mut html, before);
+                output.push_str(&format!("\n\n{}\n\n", body.trim()));
+                push_placeholder(&mut output, &mut html, after);
+            }
+            (false, _, _) => push_placeholder(&mut output, &mut html, &rendered),
+        }
+        last = whole.end();
+    }
+    output.push_str(&markdown[last..]);
+
+    Ok(Rendered {
+        markdown: output,
+        html,
+    })
+}
+
+/// Puts a placeholder for `rendered`, an html shortcode's output, in
+/// `output`, for it to be put back once the markdown is html.
+fn push_placeholder(output: &mut String, html: &mut Vec<String>, rendered: &str) {
+    output.push_str(&format!("<!--shortcode:{}-->", html.len()));
+    html.push(rendered.to_owned());
+}
+
+/// Where the fenced code blocks and code spans in `markdown` are.
+fn code_ranges(markdown: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    // the fence's character, length, and where it starts
+    let mut fence: Option<(char, usize, usize)> = None;
+    // where the text outside fences that's yet to be searched starts
+    let mut text_start = 0;
+    let mut offset = 0;
+    for line in markdown.split_inclusive('\n') {
+        let trimmed = line.trim_start_matches(' ');
+        let indented = line.len() - trimmed.len() > 3;
+        let marker = trimmed.chars().next().filter(|c| *c == '`' || *c == '~');
+        let length = marker.map_or(0, |c| trimmed.chars().take_while(|&m| m == c).count());
+        match fence {
+            None if !indented && length >= 3 => {
+                code_spans(markdown, text_start..offset, &mut ranges);
+                fence = Some((marker.unwrap(), length, offset));
+            }
+            Some((c, open, start))
+                if !indented
+                    && marker == Some(c)
+                    && length >= open
+                    && trimmed[length..].trim().is_empty() =>
+            {
+                ranges.push(start..offset + line.len());
+                fence = None;
+                text_start = offset + line.len();
+            }
+            _ => {}
+        }
+        offset += line.len();
+    }
+    match fence {
+        // an unclosed fence runs to the end
+        Some((_, _, start)) => ranges.push(start..markdown.len()),
+        None => code_spans(markdown, text_start..markdown.len(), &mut ranges),
+    }
+    ranges
+}
+
+/// Adds where the code spans in `markdown[text]` are to `ranges`: from a run
+/// of backticks to the next run of the same length.
+fn code_spans(markdown: &str, text: Range<usize>, ranges: &mut Vec<Range<usize>>) {
+    let bytes = &markdown.as_bytes()[text.clone()];
+    let run_at = |i: usize| bytes[i..].iter().take_while(|&&b| b == b'`').count();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'`' {
+            i += 1;
+            continue;
+        }
+        let open = run_at(i);
+        let mut j = i + open;
+        let mut close = None;
+        while j < bytes.len() {
+            match run_at(j) {
+                0 => j += 1,
+                run if run == open => {
+                    close = Some(j + run);
+                    break;
+                }
+                run => j += run,
+            }
+        }
+        match close {
+            Some(end) => {
+                ranges.push(text.start + i..text.start + end);
+                i = end;
+            }
+            // a run with no match is just backticks
+            None => i += open,
+        }
+    }
+}
+
+/// How a shortcode gets rendered.
+enum Renderer {
+    Template(String),
+    Builtin(builtin::Builtin),
+}
+
+/// Finds how to render shortcode `name`, and whether what it renders is
+/// markdown. Templates take precedence over built-in shortcodes.
+fn find(name: &str, tera: &Tera) -> Option<(Renderer, bool)> {
+    for (extension, is_markdown) in [("md", true), ("html", false)] {
+        let template = format!("shortcodes/{name}.{extension}");
+        if tera.get_template(&template).is_ok() {
+            return Some((Renderer::Template(template), is_markdown));
+        }
+    }
+    builtin::find(name).map(|(builtin, is_markdown)| (Renderer::Builtin(builtin), is_markdown))
+}
+
+/// Renders a single shortcode with its arguments, and body if it has one.
+fn render_one(
+    tera: &Tera,
+    renderer: &Renderer,
+    args: &str,
+    body: Option<&str>,
+    context: &tera::Context,
+) -> Result<String> {
+    let mut context = context.clone();
+    if let Some(body) = body {
+        context.insert("body", body.trim());
+    }
+    for (key, value) in parse_args(args)? {
+        context.insert(key, &value);
+    }
+    let template = match renderer {
+        Renderer::Template(template) => template,
+        Renderer::Builtin(builtin) => return builtin(&context),
+    };
+    // tera's own errors only say which template failed, the reason is further
+    // down, so flatten the whole chain into one message
+    tera.render(template, &context).map_err(|err| {
+        let mut message = err.to_string();
+        let mut source = std::error::Error::source(&err);
+        while let Some(err) = source {
+            message.push_str(&format!(": {err}"));
+            source = err.source();
+        }
+        anyhow!(message)
+    })
+}
+
+/// Parses `a="b", c=1, d=true, e=["f", "g"]` into tera values.
+fn parse_args(mut args: &str) -> Result<Vec<(String, tera::Value)>> {
+    let mut parsed = Vec::new();
+    while !args.trim().is_empty() {
+        let Some(caps) = ARG_RE.captures(args) else {
+            bail!("cannot read shortcode arguments `{}`", args.trim());
+        };
+        let value = match caps[2].strip_prefix('[') {
+            Some(list) => tera::Value::Array(
+                LITERAL_RE
+                    .find_iter(list)
+                    .map(|literal| parse_literal(literal.as_str()))
+                    .collect::<Result<_>>()?,
+            ),
+            None => parse_literal(&caps[2])?,
+        };
+        parsed.push((caps[1].to_owned(), value));
+        args = &args[caps.get(0).unwrap().end()..];
+    }
+    Ok(parsed)
+}
+
+fn parse_literal(literal: &str) -> Result<tera::Value> {
+    match literal.strip_prefix('\'') {
+        Some(string) => Ok(tera::Value::String(
+            string.trim_end_matches('\'').to_owned(),
+        )),
+        // double-quoted strings, numbers, and booleans all read as json
+        None => serde_json::from_str(literal)
+            .with_context(|| format!("cannot read shortcode argument `{literal}`")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(markdown: &str) -> Rendered {
+        let tera = Tera::default();
+        render_shortcodes(markdown, &tera, &tera::Context::new())
+            .unwrap_or_else(|err| panic!("{:#}", err.source))
+    }
+
+    #[test]
+    fn renders_shortcodes_outside_code() {
+        let rendered = render(r#"{{ youtube(id="abc") }}"#);
+        assert_eq!(rendered.markdown, "<!--shortcode:0-->");
+        assert_eq!(rendered.html.len(), 1);
+    }
+
+    #[test]
+    fn leaves_shortcodes_in_code_spans() {
+        let markdown = r#"use `{{ youtube(id="abc") }}` or ``{{ youtube(id="`") }}``"#;
+        let rendered = render(markdown);
+        assert_eq!(rendered.markdown, markdown);
+        assert!(rendered.html.is_empty());
+    }
+
+    #[test]
+    fn leaves_shortcodes_in_fences() {
+        let markdown = "```\n{{ youtube(id=\"abc\") }}\n```\n\n~~~~md\n{% details(summary=\"a\") %}\nb\n{% end %}\n~~~~\n";
+        let rendered = render(markdown);
+        assert_eq!(rendered.markdown, markdown);
+        assert!(rendered.html.is_empty());
+    }
+
+    #[test]
+    fn renders_shortcodes_after_code() {
+        let markdown = "```\n`\n```\n`x` {{ youtube(id=\"abc\") }}";
+        let rendered = render(markdown);
+        assert_eq!(rendered.markdown, "```\n`\n```\n`x` <!--shortcode:0-->");
+    }
+
+    #[test]
+    fn inserts_html_into_escaped_placeholders() {
+        let rendered = Rendered {
+            markdown: String::new(),
+            html: vec!["<div></div>".to_owned(), "<b></b>".to_owned()],
+        };
+        assert_eq!(
+            rendered
+                .insert_html("<p>&lt;!--shortcode:0--&gt;</p>\n<p>a &lt;!--shortcode:1--&gt;</p>"),
+            "<div></div>\n<p>a <b></b></p>"
+        );
+        assert_eq!(rendered.insert_html("<!--shortcode:1-->"), "<b></b>");
+    }
+
+    #[test]
+    fn unclosed_backticks_are_not_code() {
+        let rendered = render(r#"a ` b {{ youtube(id="abc") }}"#);
+        assert_eq!(rendered.html.len(), 1);
+    }
+
+    #[test]
+    fn globs_galleries_under_the_page_root() {
+        let root = std::env::temp_dir().join(format!("blog-gallery-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("trip")).unwrap();
+        std::fs::write(root.join("trip/a.jpg"), b"").unwrap();
+
+        let mut context = tera::Context::new();
+        context.insert("page", &std::collections::HashMap::from([("root", &root)]));
+        let rendered = render_shortcodes(
+            r#"{{ gallery(images="trip/*.jpg") }}"#,
+            &Tera::default(),
+            &context,
+        )
+        .unwrap_or_else(|err| panic!("{:#}", err.source));
+        // relative to the root, with `/` escaped by `tera::escape_html`
+        assert!(rendered.html[0].contains(r#"src="trip&#x2F;a.jpg""#));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn escapes_video_thumbnails() {
+        let rendered = render(r#"{{ youtube(id="abc", thumbnail="a\".jpg") }}"#);
+        assert!(rendered.html[0].contains(r#"src="a&quot;.jpg""#));
+    }
+}
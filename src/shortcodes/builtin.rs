@@ -0,0 +1,266 @@
+//! Shortcodes that come with the blog, no template needed. A template with the
+//! same name replaces the built-in one.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use log::warn;
+
+use super::BODY_SLOT;
+use crate::{previews::LinkPreview, with_previews, CONTENT_DIR};
+
+/// Renders a built-in shortcode, given its arguments and the usual context.
+pub type Builtin = fn(&tera::Context) -> Result<String>;
+
+/// Finds built-in shortcode `name`, and whether what it renders is markdown.
+pub fn find(name: &str) -> Option<(Builtin, bool)> {
+    match name {
+        "include_code" => Some((include_code, true)),
+        "gallery" => Some((gallery, false)),
+        "youtube" => Some((youtube, false)),
+        "vimeo" => Some((vimeo, false)),
+        "link_preview" => Some((link_preview, false)),
+        "details" => Some((details, false)),
+        _ => None,
+    }
+}
+
+/// Gets string argument `name`, if it was given.
+fn string_arg<'a>(context: &'a tera::Context, name: &str) -> Result<Option<&'a str>> {
+    match context.get(name) {
+        None => Ok(None),
+        Some(tera::Value::String(value)) => Ok(Some(value)),
+        Some(value) => bail!("`{name}` should be a string, not `{value}`"),
+    }
+}
+
+/// Gets string argument `name`, which has to be given.
+fn required_string_arg<'a>(context: &'a tera::Context, name: &str) -> Result<&'a str> {
+    string_arg(context, name)?.with_context(|| format!("missing argument `{name}`"))
+}
+
+/// `include_code(path="src/main.rs", lines="10-20")`: a file from the repo,
+/// or some of its lines, as a code block. The language comes from the file's
+/// extension, unless there's a `lang` argument.
+fn include_code(context: &tera::Context) -> Result<String> {
+    let path = required_string_arg(context, "path")?;
+    let contents = fs::read_to_string(path).with_context(|| format!("cannot read {path}"))?;
+    let lines: Vec<&str> = contents.lines().collect();
+
+    let (start, end) = match string_arg(context, "lines")? {
+        Some(range) => parse_line_range(range, lines.len())?,
+        None => (1, lines.len()),
+    };
+    let code = lines[start - 1..end].join("\n");
+
+    let lang = match string_arg(context, "lang")? {
+        Some(lang) => lang,
+        None => Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default(),
+    };
+
+    // the fence has to be longer than any run of backticks in the code
+    let mut longest_run = 0;
+    let mut run = 0;
+    for c in code.chars() {
+        run = if c == '`' { run + 1 } else { 0 };
+        longest_run = longest_run.max(run);
+    }
+    let fence = "`".repeat(longest_run.max(2) + 1);
+
+    Ok(format!("{fence}{lang}\n{code}\n{fence}"))
+}
+
+/// Reads `10-20`, `10-`, or `10` into first and last lines, counting from 1.
+fn parse_line_range(range: &str, line_count: usize) -> Result<(usize, usize)> {
+    let parse = |n: &str| {
+        n.trim()
+            .parse::<usize>()
+            .with_context(|| format!("cannot read line range `{range}`"))
+    };
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) if end.trim().is_empty() => (parse(start)?, line_count),
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => (parse(range)?, parse(range)?),
+    };
+    if start == 0 || start > end || end > line_count {
+        bail!("line range `{range}` is outside the file's {line_count} lines");
+    }
+    Ok((start, end))
+}
+
+/// `gallery(images="trip/*.jpg")` or `gallery(images=["a.jpg", "b.jpg"])`: a
+/// grid of thumbnails, each linking to its full-size image. Globs are relative
+/// to the page's content directory, like image paths in markdown.
+/// `alts=["...", ...]` gives the images' alt text, in the same order.
+fn gallery(context: &tera::Context) -> Result<String> {
+    let root = context
+        .get("page")
+        .and_then(|page| page.get("root")?.as_str())
+        .map_or_else(|| CONTENT_DIR.clone(), PathBuf::from);
+    let images = match context.get("images") {
+        Some(tera::Value::String(pattern)) => {
+            let mut images = Vec::new();
+            for entry in globwalk::GlobWalkerBuilder::from_patterns(&root, &[pattern]).build()? {
+                let path = entry?.into_path();
+                let path = path.strip_prefix(&root).unwrap_or(&path);
+                images.push(path.to_string_lossy().into_owned());
+            }
+            images.sort();
+            if images.is_empty() {
+                bail!("no images match `{pattern}`");
+            }
+            images
+        }
+        Some(tera::Value::Array(images)) => string_list(images, "an image path")?,
+        Some(images) => bail!("`images` should be a glob or a list, not `{images}`"),
+        None => bail!("missing argument `images`"),
+    };
+    let alts = match context.get("alts") {
+        Some(tera::Value::Array(alts)) => string_list(alts, "alt text")?,
+        Some(alts) => bail!("`alts` should be a list, not `{alts}`"),
+        None => Vec::new(),
+    };
+    if alts.len() > images.len() {
+        bail!("there are more `alts` than `images`");
+    }
+
+    // the images get copied and sized along with the rest of the page's, and
+    // `data-thumbnail` has a smaller copy shown instead
+    let mut html = String::from(r#"<div class="gallery">"#);
+    for (i, image) in images.iter().enumerate() {
+        let image = tera::escape_html(image);
+        let alt = tera::escape_html(alts.get(i).map_or("", String::as_str));
+        html.push_str(&format!(
+            r#"<a href="{image}"><img src="{image}" alt="{alt}" loading="lazy" data-thumbnail></a>"#
+        ));
+    }
+    html.push_str("</div>");
+    Ok(html)
+}
+
+/// Reads a list argument of strings, each of which is `what`.
+fn string_list(values: &[tera::Value], what: &str) -> Result<Vec<String>> {
+    values
+        .iter()
+        .map(|value| match value {
+            tera::Value::String(value) => Ok(value.clone()),
+            value => bail!("`{value}` isn't {what}"),
+        })
+        .collect()
+}
+
+/// `youtube(id="dQw4w9WgXcQ")`, see [`video`].
+fn youtube(context: &tera::Context) -> Result<String> {
+    let id = video_id(context)?;
+    video(
+        context,
+        &format!("https://www.youtube.com/watch?v={id}"),
+        &format!("https://www.youtube-nocookie.com/embed/{id}?autoplay=1"),
+    )
+}
+
+/// `vimeo(id="76979871")`, see [`video`].
+fn vimeo(context: &tera::Context) -> Result<String> {
+    let id = video_id(context)?;
+    video(
+        context,
+        &format!("https://vimeo.com/{id}"),
+        &format!("https://player.vimeo.com/video/{id}?autoplay=1&dnt=1"),
+    )
+}
+
+/// Gets a video's `id`, which can be a number, as a string.
+fn video_id(context: &tera::Context) -> Result<String> {
+    let id = match context.get("id") {
+        Some(tera::Value::String(id)) => id.clone(),
+        Some(tera::Value::Number(id)) => id.to_string(),
+        Some(id) => bail!("`id` should be a string, not `{id}`"),
+        None => bail!("missing argument `id`"),
+    };
+    // it goes straight into urls and attributes
+    if !id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!("`{id}` doesn't look like a video id");
+    }
+    Ok(id)
+}
+
+/// A placeholder that only loads the video player once it's clicked, so
+/// nothing is fetched from the video site before then. The placeholder shows
+/// the `title` argument, over the `thumbnail` image if there is one (a local
+/// one, copied like any other image). Without javascript, it's just a link to
+/// the video.
+fn video(context: &tera::Context, watch_url: &str, embed_url: &str) -> Result<String> {
+    let title = string_arg(context, "title")?.unwrap_or("Play video");
+    let title = tera::escape_html(title);
+    let thumbnail = match string_arg(context, "thumbnail")? {
+        Some(thumbnail) => format!(
+            r#"<img src="{}" alt="" loading="lazy">"#,
+            tera::escape_html(thumbnail)
+        ),
+        None => String::new(),
+    };
+    let load = "var player = document.createElement('iframe'); \
+        player.src = this.dataset.embed; \
+        player.allow = 'autoplay; fullscreen; picture-in-picture'; \
+        player.allowFullscreen = true; \
+        player.title = this.title; \
+        this.replaceWith(player); \
+        return false";
+    Ok(format!(
+        r#"<div class="video"><a href="{watch_url}" data-embed="{embed_url}" title="{title}" onclick="{load}">{thumbnail}<span>&#9654; {title}</span></a></div>"#
+    ))
+}
+
+/// `link_preview(url="https://example.com/")`: a card with the linked page's
+/// title, description, and image, fetched once and cached. If it can't be
+/// fetched, the card just has the url, and it's tried again next build.
+fn link_preview(context: &tera::Context) -> Result<String> {
+    let url = required_string_arg(context, "url")?;
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        bail!("`{url}` isn't a web address");
+    }
+    let preview = with_previews(|previews| previews.get_or_fetch(url));
+    let preview = preview.unwrap_or_else(|err| {
+        warn!("{err:#}");
+        LinkPreview::default()
+    });
+
+    let title = tera::escape_html(preview.title.as_deref().unwrap_or(url));
+    let url = tera::escape_html(url);
+    let mut html = format!(r#"<a class="link-preview" href="{url}">"#);
+    if let Some(image) = &preview.image {
+        let image = tera::escape_html(image);
+        html.push_str(&format!(r#"<img src="{image}" alt="" loading="lazy">"#));
+    }
+    html.push_str(&format!("<strong>{title}</strong>"));
+    if let Some(description) = &preview.description {
+        html.push_str(&format!("<span>{}</span>", tera::escape_html(description)));
+    }
+    html.push_str("</a>");
+    Ok(html)
+}
+
+/// `{% details(summary="Spoilers") %}...{% end %}`: a collapsed section,
+/// with its body rendered as markdown. `open=true` starts it expanded.
+fn details(context: &tera::Context) -> Result<String> {
+    let summary = tera::escape_html(string_arg(context, "summary")?.unwrap_or("Details"));
+    let open = match context.get("open") {
+        Some(tera::Value::Bool(true)) => " open",
+        _ => "",
+    };
+    // the body stays markdown, between the html, whether or not the markdown
+    // allows html
+    Ok(format!(
+        "<details{open}><summary>{summary}</summary>{BODY_SLOT}</details>"
+    ))
+}
@@ -0,0 +1,210 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use anyhow::{Context, Result};
+use log::info;
+
+use crate::{checksum, config, config::ChangeDetection, state::Copied};
+
+enum Staged {
+    Contents(String),
+    Bytes(Vec<u8>),
+    CopyOf(PathBuf),
+}
+
+/// Files a build is going to write, held back until everything has rendered,
+/// so that a failed build leaves the output directory as it was.
+#[derive(Default)]
+pub struct Staging {
+    files: Vec<(PathBuf, Staged)>,
+    /// The slug of the page being built, which the files written now are for.
+    page: Option<String>,
+    /// The files written for each page, by slug.
+    page_files: BTreeMap<String, BTreeSet<PathBuf>>,
+}
+
+impl Staging {
+    /// Counts the files written from now on as `page`'s, or as no page's.
+    pub fn set_page(&mut self, page: Option<&str>) {
+        self.page = page.map(str::to_owned);
+    }
+
+    pub fn write<P: AsRef<Path>>(&mut self, path: P, contents: String) {
+        self.push(path.as_ref().to_owned(), Staged::Contents(contents));
+    }
+
+    pub fn write_bytes<P: AsRef<Path>>(&mut self, path: P, contents: Vec<u8>) {
+        self.push(path.as_ref().to_owned(), Staged::Bytes(contents));
+    }
+
+    pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, from: P, to: Q) {
+        self.push(
+            to.as_ref().to_owned(),
+            Staged::CopyOf(from.as_ref().to_owned()),
+        );
+    }
+
+    fn push(&mut self, path: PathBuf, staged: Staged) {
+        if let Some(page) = &self.page {
+            self.page_files
+                .entry(page.clone())
+                .or_default()
+                .insert(path.clone());
+        }
+        self.files.push((path, staged));
+    }
+
+    /// The files written for each page, by slug.
+    pub fn page_files(&self) -> &BTreeMap<String, BTreeSet<PathBuf>> {
+        &self.page_files
+    }
+
+    /// Whether `path` is one of the files to be written.
+    pub fn will_write(&self, path: &Path) -> bool {
+        self.files.iter().any(|(staged, _)| staged == path)
+    }
+
+    /// What's staged for `path`, or `None` if nothing is.
+    pub fn contents(&self, path: &Path) -> Option<Cow<'_, [u8]>> {
+        let (_, staged) = self.files.iter().rev().find(|(staged, _)| staged == path)?;
+        match staged {
+            Staged::Contents(contents) => Some(Cow::Borrowed(contents.as_bytes())),
+            Staged::Bytes(contents) => Some(Cow::Borrowed(contents)),
+            Staged::CopyOf(from) => fs::read(from).ok().map(Cow::Owned),
+        }
+    }
+
+    /// The files to be copied from.
+    pub fn copy_sources(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().filter_map(|(_, staged)| match staged {
+            Staged::CopyOf(from) => Some(from.as_path()),
+            Staged::Contents(_) | Staged::Bytes(_) => None,
+        })
+    }
+
+    /// The html files to be written, with their contents.
+    pub fn html_files(&self) -> impl Iterator<Item = (&Path, &str)> {
+        self.files.iter().filter_map(|(path, staged)| match staged {
+            Staged::Contents(contents) if path.extension().is_some_and(|e| e == "html") => {
+                Some((path.as_path(), contents.as_str()))
+            }
+            _ => None,
+        })
+    }
+
+    /// Writes everything out, returning the paths written.
+    ///
+    /// Copies are skipped if `copied`, what previous builds copied, shows
+    /// the destination already has the same contents; it's updated with
+    /// this build's. Files are hashed and written on as many threads as
+    /// there are cores.
+    pub fn commit(self, copied: &mut BTreeMap<PathBuf, Copied>) -> Result<Vec<PathBuf>> {
+        let previously_copied = std::mem::take(copied);
+
+        // a path staged twice ends up with what was staged last, and two
+        // threads mustn't write it at once
+        let mut seen = HashSet::new();
+        let mut files: Vec<(PathBuf, Staged)> = self
+            .files
+            .into_iter()
+            .rev()
+            .filter(|(path, _)| seen.insert(path.clone()))
+            .collect();
+        files.reverse();
+
+        let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let chunk_size = files.len().div_ceil(threads).max(1);
+        let previously_copied = &previously_copied;
+        let results: Vec<Result<Option<Copied>>> = thread::scope(|scope| {
+            let handles: Vec<_> = files
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|(path, staged)| {
+                                write_file(path, staged, previously_copied.get(path))
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("a thread writing files panicked"))
+                .collect()
+        });
+
+        let mut written = Vec::new();
+        for ((path, _), result) in files.into_iter().zip(results) {
+            if let Some(file) = result? {
+                copied.insert(path.clone(), file);
+            }
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+/// Writes `staged` to `path`. Returns what was copied, for copies.
+fn write_file(path: &Path, staged: &Staged, previous: Option<&Copied>) -> Result<Option<Copied>> {
+    match staged {
+        Staged::Contents(contents) => write_atomically(path, contents.as_bytes())?,
+        Staged::Bytes(contents) => write_atomically(path, contents)?,
+        Staged::CopyOf(from) => return copy_file(from, path, previous).map(Some),
+    }
+    Ok(None)
+}
+
+/// Copies `from` to `to`, unless `previous`, what was last copied there,
+/// shows it's already there.
+fn copy_file(from: &Path, to: &Path, previous: Option<&Copied>) -> Result<Copied> {
+    let metadata =
+        fs::metadata(from).with_context(|| format!("cannot copy {}", from.to_string_lossy()))?;
+    let destination_matches = fs::metadata(to).is_ok_and(|m| m.len() == metadata.len());
+
+    // with the same size and modification time, the source is taken to be
+    // the same without reading it
+    let modified = metadata.modified().ok();
+    let same_metadata = previous.filter(|previous| {
+        previous.size == metadata.len() && modified.is_some() && previous.modified == modified
+    });
+    if let Some(previous) = same_metadata
+        .filter(|_| config().change_detection == ChangeDetection::Metadata && destination_matches)
+    {
+        return Ok(previous.clone());
+    }
+
+    let contents =
+        fs::read(from).with_context(|| format!("cannot copy {}", from.to_string_lossy()))?;
+    let checksum = checksum::checksum(&contents);
+    if !destination_matches || previous.is_none_or(|previous| previous.checksum != checksum) {
+        write_atomically(to, &contents)?;
+    }
+    Ok(Copied {
+        checksum,
+        size: metadata.len(),
+        modified,
+    })
+}
+
+/// Writes `contents` next to `path` and then renames it over it, so an
+/// interrupted build never leaves a half-written file behind.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_path = path.with_file_name(format!(".{file_name}.tmp"));
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path)?;
+
+    info!("Writing {}", path.as_os_str().to_string_lossy());
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+    path::PathBuf,
+    time::SystemTime,
+};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Changes to the state file's layout, in order: the first turns a file
+/// with no `version` into version 1, and so on. A change that needs old
+/// files rewritten adds one here rather than making everyone delete theirs.
+const MIGRATIONS: [fn(&mut Value); 3] = [
+    // files from before there were versions are laid out the same
+    |_| {},
+    // `copied` went from checksums to `Copied`s
+    |state| {
+        if let Some(copied) = state.get_mut("copied").and_then(Value::as_object_mut) {
+            for value in copied.values_mut() {
+                *value = serde_json::json!({ "checksum": value.take() });
+            }
+        }
+    },
+    // webmentions are sent for what changed since they were last sent, not
+    // since the last build; taking everything built so far to have been
+    // mentioned saves sending them all again
+    |state| {
+        if let Some(checksums) = state.get("checksums").cloned() {
+            state["mentioned"] = checksums;
+        }
+    },
+];
+
+/// The version of the state file this build writes.
+const VERSION: u64 = MIGRATIONS.len() as u64;
+
+/// What previous builds left behind, kept in `.cache/state.json`.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct State {
+    /// Every file a build has written to the output directory. Anything else
+    /// in there was put there by hand.
+    pub outputs: BTreeSet<PathBuf>,
+    /// The files each page was built into, by slug: its `index.html`, the
+    /// images copied for it, and everything else. Files a page no longer
+    /// has are removed.
+    pub pages: BTreeMap<String, BTreeSet<PathBuf>>,
+    /// A checksum of each page's body, by url, to tell which pages changed.
+    pub checksums: BTreeMap<String, String>,
+    /// A checksum of each page's body, by url, as it was when webmentions
+    /// were last sent for it.
+    pub mentioned: BTreeMap<String, String>,
+    /// A checksum of each file `blog deploy` last uploaded, by its path under
+    /// the output directory.
+    pub deployed: BTreeMap<PathBuf, String>,
+    /// What was copied to each file in the output, e.g. a page's images, so
+    /// unchanged ones aren't copied again.
+    pub copied: BTreeMap<PathBuf, Copied>,
+    /// A checksum of each file with compressed copies, as it was compressed.
+    pub compressed: BTreeMap<PathBuf, String>,
+}
+
+/// A file as it was copied into the output.
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct Copied {
+    pub checksum: String,
+    /// The source's size and modification time, for `change_detection =
+    /// "metadata"`.
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl State {
+    /// Loads state from `path`, bringing it up to date if an older version
+    /// wrote it, or starts fresh if there isn't any.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<State> {
+        let Ok(contents) = fs::read_to_string(&path) else {
+            return Ok(State::default());
+        };
+        let mut state: Value = serde_json::from_str(&contents)?;
+        let version = state.get("version").and_then(Value::as_u64).unwrap_or(0);
+        if version > VERSION {
+            bail!(
+                "{} is from a newer version of blog; delete it to start over",
+                path.as_ref().to_string_lossy()
+            );
+        }
+        for migrate in &MIGRATIONS[version as usize..] {
+            migrate(&mut state);
+        }
+        Ok(serde_json::from_value(state)?)
+    }
+
+    /// Writes state to `path`, as the current version.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut state = serde_json::to_value(self)?;
+        state["version"] = VERSION.into();
+        fs::write(path, serde_json::to_string_pretty(&state)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads `json` as a state file, named `name` so tests don't share one.
+    fn load(name: &str, json: &str) -> Result<State> {
+        let path = std::env::temp_dir().join(format!("blog-{name}-{}.json", std::process::id()));
+        fs::write(&path, json)?;
+        let state = State::load(&path);
+        fs::remove_file(&path)?;
+        state
+    }
+
+    #[test]
+    fn migrates_unversioned_state() {
+        let state = load(
+            "unversioned",
+            r#"{"checksums": {"/a/": "1"}, "copied": {"a.png": "2"}}"#,
+        )
+        .unwrap();
+        assert_eq!(state.copied[Path::new("a.png")].checksum, "2");
+        assert_eq!(state.mentioned["/a/"], "1");
+    }
+
+    #[test]
+    fn refuses_newer_state() {
+        assert!(load("newer", r#"{"version": 1000}"#).is_err());
+    }
+}
@@ -0,0 +1,86 @@
+//! `blog stats`: a summary of what's in the site.
+
+use std::collections::BTreeMap;
+
+use chrono::Datelike;
+
+use crate::{Content, Page};
+
+/// The longest bar in a histogram.
+const BAR_WIDTH: usize = 40;
+
+/// Prints how many posts and drafts there are, how long the posts are, and
+/// how they're spread over years and tags.
+pub fn print(content: &Content) {
+    let (mut posts, pages): (Vec<&Page>, Vec<&Page>) =
+        content.pages.iter().partition(|page| !page.standalone);
+    posts.sort_by_key(|post| post.date);
+
+    println!(
+        "{} posts, {} other pages, {} drafts",
+        posts.len(),
+        pages.len(),
+        content.drafts.len()
+    );
+
+    let words: Vec<usize> = posts.iter().map(|post| word_count(post)).collect();
+    let total: usize = words.iter().sum();
+    println!();
+    println!(
+        "{total} words, {} per post",
+        total.checked_div(posts.len()).unwrap_or_default()
+    );
+    for (post, words) in posts.iter().zip(&words) {
+        let date = post.date.map(|date| date.to_string()).unwrap_or_default();
+        println!("  {date}  {words:>6}  {}", post.front_matter.title);
+    }
+
+    let mut years = BTreeMap::new();
+    for date in posts.iter().filter_map(|post| post.date) {
+        *years.entry(date.year().to_string()).or_default() += 1;
+    }
+    println!();
+    println!("Posts per year");
+    histogram(&years.into_iter().collect::<Vec<_>>());
+
+    let mut tags: BTreeMap<String, usize> = BTreeMap::new();
+    for post in &posts {
+        if let Some(tera::Value::Array(post_tags)) = post.front_matter.extra.get("tags") {
+            for tag in post_tags.iter().filter_map(|tag| tag.as_str()) {
+                *tags.entry(tag.to_owned()).or_default() += 1;
+            }
+        }
+    }
+    if !tags.is_empty() {
+        // most used first
+        let mut tags: Vec<_> = tags.into_iter().collect();
+        tags.sort_by(|(_, a), (_, b)| b.cmp(a));
+        println!();
+        println!("Tags");
+        histogram(&tags);
+    }
+}
+
+/// Words in a page's markdown, not counting code blocks.
+fn word_count(page: &Page) -> usize {
+    let mut in_code = false;
+    let mut words = 0;
+    for line in page.contents.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+        } else if !in_code {
+            words += line.split_whitespace().count();
+        }
+    }
+    words
+}
+
+fn histogram(counts: &[(String, usize)]) {
+    let width = counts.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let most = counts.iter().map(|(_, count)| *count).max().unwrap_or(1);
+    for (name, count) in counts {
+        let bar = "#".repeat((count * BAR_WIDTH).div_ceil(most));
+        println!("  {name:width$}  {bar} {count}");
+    }
+}
@@ -0,0 +1,100 @@
+//! Themes in `themes/<name>/`: templates, files for the output directory,
+//! and a syntax highlighting theme, all of which the site's own override.
+//!
+//! A theme can extend another by naming it as `parent` in its `theme.toml`,
+//! and override what it likes of it the same way.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+use crate::{html, staging::Staging, state::State, THEME_DIR, WEBSITE_DIR};
+
+/// A theme's `theme.toml`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ThemeConfig {
+    /// The theme this one extends.
+    parent: Option<String>,
+}
+
+/// The directories of theme `name` and the themes it extends, most specific
+/// first.
+pub fn chain(name: &str) -> Result<Vec<PathBuf>> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    let mut name = name.to_owned();
+    loop {
+        let dir = THEME_DIR.join(&name);
+        if dirs.contains(&dir) {
+            bail!("theme `{name}` ends up extending itself");
+        }
+        if !dir.is_dir() {
+            bail!("there's no theme {}", dir.to_string_lossy());
+        }
+
+        let config_path = dir.join("theme.toml");
+        let config: ThemeConfig = match fs::read_to_string(&config_path) {
+            Ok(contents) => toml::from_str(&contents)
+                .with_context(|| format!("cannot parse {}", config_path.to_string_lossy()))?,
+            Err(_) => ThemeConfig::default(),
+        };
+        dirs.push(dir);
+        match config.parent {
+            Some(parent) => name = parent,
+            None => return Ok(dirs),
+        }
+    }
+}
+
+/// Stages copying everything in the `static/` of each of `themes` into the
+/// output directory, and the first `syntax.tmTheme` as `syntax.css`. The
+/// site's own files win: ones the build already writes, and ones put in the
+/// output directory by hand. Then each theme's win over those it extends.
+pub fn stage_files(themes: &[PathBuf], state: &State, staging: &mut Staging) -> Result<()> {
+    let is_overridden =
+        |path: &Path| staging.will_write(path) || (path.exists() && !state.outputs.contains(path));
+
+    let css_path = WEBSITE_DIR.join("syntax.css");
+    let mut css = None;
+    let syntax_theme = themes
+        .iter()
+        .map(|dir| dir.join("syntax.tmTheme"))
+        .find(|path| path.is_file());
+    if let Some(syntax_theme) = syntax_theme.filter(|_| !is_overridden(&css_path)) {
+        let theme = syntect::highlighting::ThemeSet::get_theme(&syntax_theme)
+            .with_context(|| format!("cannot load {}", syntax_theme.to_string_lossy()))?;
+        css = Some(syntect::html::css_for_theme_with_class_style(
+            &theme,
+            html::SYNTECT_CLASSSTYLE,
+        )?);
+    }
+
+    let mut copies: Vec<(PathBuf, PathBuf)> = Vec::new();
+    for theme_dir in themes {
+        let static_dir = theme_dir.join("static");
+        let entries = static_dir.is_dir().then(|| WalkDir::new(&static_dir));
+        for entry in entries.into_iter().flatten() {
+            let path = entry?.into_path();
+            if !path.is_file() {
+                continue;
+            }
+            let to = WEBSITE_DIR.join(path.strip_prefix(&static_dir)?);
+            if !is_overridden(&to) && !copies.iter().any(|(_, staged)| *staged == to) {
+                copies.push((path, to));
+            }
+        }
+    }
+
+    if let Some(css) = css {
+        staging.write(css_path, css);
+    }
+    for (from, to) in copies {
+        staging.copy(from, to);
+    }
+    Ok(())
+}
@@ -0,0 +1,81 @@
+//! Smaller copies of gallery images, so a grid of them doesn't load every
+//! image at full size. They're kept in `.cache/thumbnails/`, by a hash of the
+//! image, and only made again when it changes.
+
+use std::{fs, io::Cursor, path::Path, path::PathBuf};
+
+use anyhow::{Context, Result};
+use image::{DynamicImage, ImageFormat};
+
+use crate::{checksum::Hasher, CACHE_DIR};
+
+/// Thumbnails fit in a square this big, in pixels.
+pub const SIZE: u32 = 480;
+
+/// A thumbnail of the image at `path`, and its width and height, or `None`
+/// if the image is small enough as it is.
+pub fn thumbnail(path: &Path) -> Result<Option<(PathBuf, u32, u32)>> {
+    thumbnail_in(path, &CACHE_DIR.join("thumbnails"))
+}
+
+fn thumbnail_in(path: &Path, cache_dir: &Path) -> Result<Option<(PathBuf, u32, u32)>> {
+    let (width, height) = image::image_dimensions(path)
+        .with_context(|| format!("cannot read {}", path.to_string_lossy()))?;
+    if width <= SIZE && height <= SIZE {
+        return Ok(None);
+    }
+    let format = ImageFormat::from_path(path)?;
+    let contents = fs::read(path)?;
+
+    let mut hasher = Hasher::new();
+    hasher.update(SIZE.to_string());
+    hasher.update(&contents);
+    let extension = format.extensions_str().first().copied().unwrap_or("png");
+    let cached = cache_dir.join(format!("{}.{extension}", hasher.finalize()));
+
+    if !cached.exists() {
+        let image = image::load_from_memory_with_format(&contents, format)?;
+        let thumbnail = image.thumbnail(SIZE, SIZE);
+        // jpegs can't have an alpha channel
+        let thumbnail = match format {
+            ImageFormat::Jpeg => DynamicImage::ImageRgb8(thumbnail.to_rgb8()),
+            _ => thumbnail,
+        };
+        let mut encoded = Cursor::new(Vec::new());
+        thumbnail.write_to(&mut encoded, format)?;
+        fs::create_dir_all(cache_dir)?;
+        fs::write(&cached, encoded.into_inner())?;
+    }
+    let (width, height) = image::image_dimensions(&cached)?;
+    Ok(Some((cached, width, height)))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    #[test]
+    fn shrinks_big_images_only() {
+        crate::load_default_config();
+        let dir = std::env::temp_dir().join(format!("blog-thumbnails-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cache_dir = dir.join("cache");
+
+        let big = dir.join("big.jpg");
+        RgbImage::new(1200, 600).save(&big).unwrap();
+        let (cached, width, height) = thumbnail_in(&big, &cache_dir).unwrap().unwrap();
+        assert_eq!((width, height), (SIZE, SIZE / 2));
+        assert_eq!(cached.extension().unwrap(), "jpg");
+        // a second time, it's the same file
+        let again = thumbnail_in(&big, &cache_dir).unwrap().unwrap();
+        assert_eq!(again.0, cached);
+
+        let small = dir.join("small.png");
+        RgbImage::new(100, 80).save(&small).unwrap();
+        assert!(thumbnail_in(&small, &cache_dir).unwrap().is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
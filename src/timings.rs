@@ -0,0 +1,111 @@
+//! How long each part of a build takes, reported with `--timings`.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy)]
+pub enum Phase {
+    Markdown,
+    Highlighting,
+    Postprocessing,
+    Templates,
+    Copying,
+    Compressing,
+    State,
+}
+
+const PHASES: [&str; 7] = [
+    "markdown",
+    "highlighting",
+    "postprocessing",
+    "templates",
+    "copying",
+    "compressing",
+    "state",
+];
+
+struct Timings {
+    /// The page being rendered, if any.
+    page: Option<PathBuf>,
+    pages: Vec<(PathBuf, [Duration; 7])>,
+    /// Time spent outside of any page.
+    other: [Duration; 7],
+}
+
+static TIMINGS: Mutex<Timings> = Mutex::new(Timings {
+    page: None,
+    pages: Vec::new(),
+    other: [Duration::ZERO; 7],
+});
+
+/// Forgets the timings of any earlier build.
+pub fn reset() {
+    let mut timings = TIMINGS.lock().unwrap();
+    timings.page = None;
+    timings.pages.clear();
+    timings.other = [Duration::ZERO; 7];
+}
+
+/// Counts what's timed from now on towards the page at `path`, until
+/// [`end_page`].
+pub fn start_page(path: &Path) {
+    let mut timings = TIMINGS.lock().unwrap();
+    timings.page = Some(path.to_owned());
+    timings.pages.push((path.to_owned(), [Duration::ZERO; 7]));
+}
+
+pub fn end_page() {
+    TIMINGS.lock().unwrap().page = None;
+}
+
+/// Runs `f`, counting how long it takes towards `phase`.
+pub fn time<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+
+    let mut timings = TIMINGS.lock().unwrap();
+    let durations = match timings.page.is_some() {
+        true => &mut timings.pages.last_mut().unwrap().1,
+        false => &mut timings.other,
+    };
+    durations[phase as usize] += elapsed;
+    result
+}
+
+/// Prints how long each phase took, for each page and in total, in
+/// milliseconds.
+pub fn report() {
+    let timings = TIMINGS.lock().unwrap();
+    let width = timings
+        .pages
+        .iter()
+        .map(|(path, _)| path.to_string_lossy().len())
+        .max()
+        .unwrap_or_default()
+        .max("outside pages".len());
+
+    let row = |name: &str, durations: &[Duration; 7]| {
+        let mut row = format!("{name:width$}");
+        for (phase, duration) in PHASES.iter().zip(durations) {
+            let millis = duration.as_secs_f64() * 1000.0;
+            row.push_str(&format!("  {millis:>w$.1}", w = phase.len()));
+        }
+        println!("{row}");
+    };
+
+    println!();
+    println!("{:width$}  {}", "(ms)", PHASES.join("  "));
+    let mut total = timings.other;
+    for (path, durations) in &timings.pages {
+        row(&path.to_string_lossy(), durations);
+        for (total, duration) in total.iter_mut().zip(durations) {
+            *total += *duration;
+        }
+    }
+    row("outside pages", &timings.other);
+    row("total", &total);
+}
@@ -0,0 +1,109 @@
+//! Sending webmentions to the sites a page links to, so they can show it as
+//! a reply or mention. See <https://www.w3.org/TR/webmention/>.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use kuchikiki::traits::TendrilSink;
+use log::{debug, info, warn};
+
+use crate::config;
+
+/// Sends a webmention from `source`, a page's full url, to every other site
+/// its `html` body links to that accepts them. Returns whether they all went
+/// out.
+pub fn send(source: &str, html: &str) -> bool {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(15))
+        .build();
+
+    let document = kuchikiki::parse_html().one(html);
+    let mut targets: Vec<String> = document
+        .select("a[href]")
+        .unwrap()
+        .filter_map(|a| a.attributes.borrow().get("href").map(str::to_owned))
+        .filter(|href| config().is_external(href) && !href.starts_with("//"))
+        .collect();
+    targets.sort();
+    targets.dedup();
+
+    let mut sent = true;
+    for target in targets {
+        let endpoint = match discover_endpoint(&agent, &target) {
+            Ok(Some(endpoint)) => endpoint,
+            Ok(None) => {
+                debug!("{target} doesn't take webmentions");
+                continue;
+            }
+            Err(err) => {
+                warn!("cannot find a webmention endpoint: {err}");
+                sent = false;
+                continue;
+            }
+        };
+        match agent
+            .post(&endpoint)
+            .send_form(&[("source", source), ("target", &target)])
+        {
+            Ok(_) => info!("Sent a webmention for {source} to {target}"),
+            Err(err) => {
+                warn!("cannot send a webmention: {err}");
+                sent = false;
+            }
+        }
+    }
+    sent
+}
+
+/// The webmention endpoint `target` advertises, in its `Link` headers or in
+/// a `<link>` or `<a>` with `rel="webmention"`, if it has one.
+fn discover_endpoint(agent: &ureq::Agent, target: &str) -> Result<Option<String>> {
+    let response = agent.get(target).call()?;
+    // relative endpoints are relative to wherever redirects ended up
+    let base = url::Url::parse(response.get_url())?;
+
+    let mut endpoint = None;
+    for header in response.all("link") {
+        for link in header.split(',') {
+            let Some((url, params)) = link.split_once(';') else {
+                continue;
+            };
+            if params.split(';').any(|param| {
+                let (name, value) = param.split_once('=').unwrap_or_default();
+                name.trim() == "rel"
+                    && value
+                        .trim_matches(|c: char| c == '"' || c.is_whitespace())
+                        .split_whitespace()
+                        .any(|rel| rel == "webmention")
+            }) {
+                endpoint = Some(url.trim().trim_matches(['<', '>']).to_owned());
+                break;
+            }
+        }
+        if endpoint.is_some() {
+            break;
+        }
+    }
+
+    let is_html = response.content_type() == "text/html";
+    if endpoint.is_none() && is_html {
+        let document = kuchikiki::parse_html().one(response.into_string()?);
+        endpoint = document
+            .select("link[rel][href], a[rel][href]")
+            .unwrap()
+            .find(|element| {
+                let attributes = element.attributes.borrow();
+                let rel = attributes.get("rel").unwrap_or_default();
+                rel.split_whitespace().any(|rel| rel == "webmention")
+            })
+            .map(|element| {
+                let attributes = element.attributes.borrow();
+                attributes.get("href").unwrap_or_default().to_owned()
+            });
+    }
+
+    Ok(match endpoint {
+        Some(endpoint) => Some(base.join(&endpoint)?.to_string()),
+        None => None,
+    })
+}